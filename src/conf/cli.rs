@@ -47,15 +47,51 @@ pub enum Commands {
         #[arg(long)]
         payload: String,
     },
-    Storage,
+    Storage {
+        #[arg(long)]
+        per_module: bool,
+        #[command(subcommand)]
+        action: Option<StorageAction>,
+    },
+    Verify {
+        #[arg(long)]
+        repair: bool,
+        #[arg(long)]
+        force: bool,
+        #[arg(long)]
+        text: bool,
+    },
+    Resync {
+        #[arg(long)]
+        module: Vec<String>,
+        #[arg(long)]
+        force: bool,
+    },
     Modules,
     Conflicts,
     Diagnostics,
+    Capabilities,
+    #[command(name = "magic-preview")]
+    MagicPreview {
+        #[arg(long)]
+        text: bool,
+    },
     #[command(name = "system-action")]
     SystemAction {
         #[arg(long)]
         action: String,
         #[arg(long)]
         value: Option<String>,
+        #[arg(long)]
+        text: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+
+pub enum StorageAction {
+    Bench {
+        #[arg(long)]
+        text: bool,
     },
 }
@@ -11,8 +11,11 @@ use crate::{
         cli::Cli,
         config::{CONFIG_FILE_DEFAULT, Config},
     },
-    core::{executor, granary, inventory, modules, planner, storage, winnow},
-    utils,
+    core::{
+        capabilities, executor, granary, granary_diff, integrity, inventory, modules, planner,
+        storage, winnow,
+    },
+    defs, utils,
 };
 
 #[derive(Serialize)]
@@ -107,7 +110,7 @@ pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
     let _: inventory::ModuleRules =
         serde_json::from_slice(&json_bytes).context("Invalid rules JSON")?;
 
-    let rules_dir = std::path::Path::new("/data/adb/meta-hybrid/rules");
+    let rules_dir = Path::new(defs::RULES_DIR);
 
     std::fs::create_dir_all(rules_dir).context("Failed to create rules directory")?;
 
@@ -121,8 +124,50 @@ pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_storage() -> Result<()> {
-    storage::print_status().context("Failed to retrieve storage status")
+pub fn handle_storage(per_module: bool) -> Result<()> {
+    storage::print_status(per_module).context("Failed to retrieve storage status")
+}
+
+pub fn handle_storage_bench(cli: &Cli, text: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    storage::bench::run(&config, text).context("Failed to run storage benchmark")
+}
+
+pub fn handle_verify(cli: &Cli, repair: bool, force: bool, text: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let reports =
+        integrity::verify_storage(&config, repair, force).context("Failed to verify storage")?;
+
+    if text {
+        for report in &reports {
+            let status = match (report.is_ok(), report.repaired) {
+                (true, _) => "ok",
+                (false, true) => "repaired",
+                (false, false) => "failed",
+            };
+
+            println!("{:<32} {:<8} {}", report.module, status, report.summary());
+        }
+
+        return Ok(());
+    }
+
+    println!("{}", serde_json::to_string(&reports)?);
+
+    Ok(())
+}
+
+pub fn handle_resync(cli: &Cli, modules: &[String], force: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let synced =
+        integrity::resync_storage(&config, modules, force).context("Failed to re-sync storage")?;
+
+    println!("{}", serde_json::to_string(&synced)?);
+
+    Ok(())
 }
 
 pub fn handle_modules(cli: &Cli) -> Result<()> {
@@ -160,7 +205,9 @@ pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     let plan = planner::generate(&config, &module_list, &config.moduledir)
         .context("Failed to generate plan for diagnostics")?;
 
-    let issues = executor::diagnose_plan(&plan);
+    let mut issues = executor::diagnose_plan(&plan);
+
+    issues.extend(executor::diagnose_propagation(&plan, &config));
 
     let json_issues: Vec<DiagnosticIssueJson> = issues
         .into_iter()
@@ -183,7 +230,93 @@ pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Result<()> {
+pub fn handle_magic_preview(cli: &Cli, text: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let module_list = inventory::scan(&config.moduledir, &config)
+        .context("Failed to scan modules for magic preview")?;
+
+    let plan = planner::generate(&config, &module_list, &config.moduledir)
+        .context("Failed to generate plan for magic preview")?;
+
+    let preview = executor::preview_magic(&plan, &config)?;
+
+    if text {
+        for op in &preview.operations {
+            println!("{}", op);
+        }
+
+        println!("{}", preview.summary());
+
+        return Ok(());
+    }
+
+    let json = serde_json::to_string(&preview).context("Failed to serialize magic preview")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+pub fn handle_capabilities() -> Result<()> {
+    let json = serde_json::to_string(capabilities::get())
+        .context("Failed to serialize kernel capabilities")?;
+
+    println!("{}", json);
+
+    Ok(())
+}
+
+fn print_changes<T: std::fmt::Display>(title: &str, changes: &[granary_diff::Change<T>]) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("[{}]", title);
+
+    let show = |value: &Option<T>| match value {
+        Some(value) => value.to_string(),
+        None => "(unset)".to_string(),
+    };
+
+    for change in changes {
+        println!(
+            "  {}: {} -> {}",
+            change.key,
+            show(&change.before),
+            show(&change.after)
+        );
+    }
+}
+
+fn print_silo_diff(diff: &granary_diff::SiloDiff) {
+    println!("Silo {} -> {}", diff.from, diff.to);
+
+    print_changes("config", &diff.config);
+
+    print_changes("winnowing", &diff.winnowing);
+
+    for module in &diff.rules {
+        print_changes(&format!("rules: {}", module.module), &module.changes);
+    }
+
+    print_changes("modules", &diff.modules);
+
+    if diff.is_empty() {
+        println!("No differences.");
+    }
+
+    for note in &diff.notes {
+        println!("note: {}", note);
+    }
+}
+
+pub fn handle_system_action(
+    cli: &Cli,
+    action: &str,
+    value: Option<&str>,
+    text: bool,
+) -> Result<()> {
     let mut config = load_config(cli)?;
 
     match action {
@@ -211,14 +344,66 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
             }
         }
         "granary-restore" => {
-            if let Some(id) = value {
-                granary::restore_silo(id)?;
+            if let Some(val) = value {
+                let (id, modules) = match val.split_once(':') {
+                    Some((id, "*")) => (id, granary::ModuleRestore::All),
+                    Some((id, list)) => (
+                        id,
+                        granary::ModuleRestore::Only(
+                            list.split(',')
+                                .filter(|m| !m.is_empty())
+                                .map(str::to_string)
+                                .collect(),
+                        ),
+                    ),
+                    None => (val, granary::ModuleRestore::Skip),
+                };
+
+                granary::restore_silo(id, &modules)?;
 
                 println!("Silo {} restored. Please reboot.", id);
             } else {
                 bail!("Missing Silo ID");
             }
         }
+        "granary-export" => {
+            if let Some(val) = value {
+                let (id, output) = match val.split_once(':') {
+                    Some((id, path)) => (id, Some(Path::new(path))),
+                    None => (val, None),
+                };
+
+                let path = granary::export_silo(id, output)?;
+
+                log::info!("Silo {} exported to {}", id, path.display());
+
+                println!("{}", path.display());
+            } else {
+                bail!("Missing Silo ID");
+            }
+        }
+        "granary-import" => {
+            if let Some(path) = value {
+                let id = granary::import_silo(Path::new(path), &config)?;
+
+                println!("Archive imported as Silo {}.", id);
+            } else {
+                bail!("Missing archive path");
+            }
+        }
+        "granary-diff" => {
+            let Some((a, b)) = value.and_then(|val| val.split_once(':')) else {
+                bail!("Expected two Silo IDs as <a>:<b>");
+            };
+
+            let diff = granary_diff::diff_silos(a, b)?;
+
+            if text {
+                print_silo_diff(&diff);
+            } else {
+                println!("{}", serde_json::to_string(&diff)?);
+            }
+        }
         "winnow-set" => {
             if let Some(val) = value
                 && let Some((path, id)) = val.split_once(':')
@@ -3,12 +3,13 @@
 
 use std::{
     collections::HashMap,
-    fs,
+    fmt, fs,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub const CONFIG_FILE_DEFAULT: &str = "/data/adb/meta-hybrid/config.toml";
 
@@ -38,6 +39,210 @@ impl WinnowingTable {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+
+pub enum PropagationMode {
+    #[default]
+    Inherit,
+    Shared,
+    Slave,
+    Private,
+}
+
+impl PropagationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Inherit => "inherit",
+            Self::Shared => "shared",
+            Self::Slave => "slave",
+            Self::Private => "private",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+
+pub enum StorageKind {
+    Tmpfs,
+    Ext4,
+    Erofs,
+    Direct,
+}
+
+impl StorageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tmpfs => "tmpfs",
+            Self::Ext4 => "ext4",
+            Self::Erofs => "erofs",
+            Self::Direct => "direct",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+
+pub enum ErofsCompressor {
+    None,
+    Lz4,
+    Lz4hc,
+    Lzma,
+    Deflate,
+}
+
+impl ErofsCompressor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Lz4 => "lz4",
+            Self::Lz4hc => "lz4hc",
+            Self::Lzma => "lzma",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    pub fn kernel_algorithm(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Lz4 | Self::Lz4hc => Some("lz4"),
+            Self::Lzma => Some("lzma"),
+            Self::Deflate => Some("deflate"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+
+pub struct ErofsOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressor: Option<ErofsCompressor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster_size: Option<u32>,
+    #[serde(default)]
+    pub fragments: bool,
+    #[serde(default)]
+    pub dedupe: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl ErofsOptions {
+    pub fn needs_mkfs(&self) -> bool {
+        self.level.is_some()
+            || self.cluster_size.is_some()
+            || self.fragments
+            || self.dedupe
+            || !matches!(
+                self.compressor,
+                None | Some(ErofsCompressor::None | ErofsCompressor::Lz4)
+            )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+pub enum TmpfsBudget {
+    Bytes(u64),
+    Percent(u8),
+}
+
+impl TmpfsBudget {
+    pub fn resolve(&self, mem_total: Option<u64>) -> Option<u64> {
+        match self {
+            Self::Bytes(bytes) => Some(*bytes),
+            Self::Percent(percent) => mem_total.map(|total| total / 100 * *percent as u64),
+        }
+    }
+}
+
+impl FromStr for TmpfsBudget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let value = s.trim();
+
+        if let Some(percent) = value.strip_suffix('%') {
+            let percent: u8 = percent.trim().parse().context("invalid percentage")?;
+
+            if percent == 0 || percent > 100 {
+                bail!("percentage must be between 1 and 100");
+            }
+
+            return Ok(Self::Percent(percent));
+        }
+
+        let upper = value.to_ascii_uppercase();
+
+        let digits = upper.trim_end_matches(['B', 'I']);
+
+        let (number, shift) = match digits.chars().last() {
+            Some('K') => (&digits[..digits.len() - 1], 10),
+            Some('M') => (&digits[..digits.len() - 1], 20),
+            Some('G') => (&digits[..digits.len() - 1], 30),
+            _ => (digits, 0),
+        };
+
+        let number: u64 = number.trim().parse().context("invalid size")?;
+
+        match number.checked_mul(1 << shift) {
+            Some(bytes) => Ok(Self::Bytes(bytes)),
+            None => bail!("size {} is too large", value),
+        }
+    }
+}
+
+impl fmt::Display for TmpfsBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bytes(bytes) => write!(f, "{}", bytes),
+            Self::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl Serialize for TmpfsBudget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TmpfsBudget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+
+        enum NumberOrString {
+            Number(u64),
+            String(String),
+        }
+
+        match NumberOrString::deserialize(deserializer)? {
+            NumberOrString::Number(bytes) => Ok(Self::Bytes(bytes)),
+            NumberOrString::String(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backends: Vec<StorageKind>,
+    #[serde(default)]
+    pub shrink_ext4: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmpfs_budget: Option<TmpfsBudget>,
+    #[serde(default)]
+    pub erofs: ErofsOptions,
+    #[serde(default)]
+    pub sync_hash: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 
 pub struct GranaryConfig {
@@ -45,6 +250,10 @@ pub struct GranaryConfig {
     pub max_backups: usize,
     #[serde(default = "default_retention_days")]
     pub retention_days: u64,
+    #[serde(default)]
+    pub snapshot_modules: bool,
+    #[serde(default = "default_module_snapshots")]
+    pub module_snapshots: usize,
 }
 
 fn default_max_backups() -> usize {
@@ -55,11 +264,17 @@ fn default_retention_days() -> u64 {
     0
 }
 
+fn default_module_snapshots() -> usize {
+    3
+}
+
 impl Default for GranaryConfig {
     fn default() -> Self {
         Self {
             max_backups: default_max_backups(),
             retention_days: default_retention_days(),
+            snapshot_modules: false,
+            module_snapshots: default_module_snapshots(),
         }
     }
 }
@@ -90,6 +305,10 @@ pub struct Config {
     pub winnowing: WinnowingTable,
     #[serde(default)]
     pub granary: GranaryConfig,
+    #[serde(default)]
+    pub propagation: HashMap<String, PropagationMode>,
+    #[serde(default)]
+    pub storage: StorageConfig,
 }
 
 fn default_moduledir() -> PathBuf {
@@ -137,6 +356,8 @@ impl Default for Config {
             dry_run: false,
             winnowing: WinnowingTable::default(),
             granary: GranaryConfig::default(),
+            propagation: HashMap::new(),
+            storage: StorageConfig::default(),
         }
     }
 }
@@ -150,6 +371,38 @@ impl Config {
         Ok(config)
     }
 
+    pub fn propagation_for(&self, partition: &str) -> PropagationMode {
+        self.propagation.get(partition).copied().unwrap_or_default()
+    }
+
+    pub fn storage_preference(&self) -> Vec<StorageKind> {
+        if !self.storage.backends.is_empty() {
+            let mut preference = Vec::new();
+
+            for kind in &self.storage.backends {
+                if !preference.contains(kind) {
+                    preference.push(*kind);
+                }
+            }
+
+            return preference;
+        }
+
+        let mut preference = Vec::new();
+
+        if self.use_erofs {
+            preference.push(StorageKind::Erofs);
+        }
+
+        if !self.force_ext4 {
+            preference.push(StorageKind::Tmpfs);
+        }
+
+        preference.push(StorageKind::Ext4);
+
+        preference
+    }
+
     pub fn load_default() -> Result<Self> {
         Self::from_file(CONFIG_FILE_DEFAULT)
     }
@@ -0,0 +1,432 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::fs::{AtFlags, CWD, FileType, Gid, Mode, Uid, chmod, chownat};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use extattr::{Flags as XattrFlags, lsetxattr};
+
+use crate::{defs, erofs::EntryMeta, selinux};
+
+const CAPABILITY_XATTR: &str = "security.capability";
+
+const VFS_CAP_REVISION_2: u32 = 0x0200_0000;
+
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+const CAPABILITY_NAMES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+
+pub struct AttrRule {
+    pub path: String,
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub mode: Option<u32>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+
+pub struct AttrManifest {
+    #[serde(default)]
+    pub rules: Vec<AttrRule>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+
+pub struct ResolvedAttrs {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub context: Option<String>,
+    pub capabilities: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+
+pub struct AttrReport {
+    pub applied: usize,
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Default)]
+
+pub struct AttrIndex {
+    manifests: HashMap<PathBuf, AttrManifest>,
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(raw) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    let digits = raw.trim().trim_start_matches("0o");
+
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid octal mode '{}'", raw)))
+}
+
+fn glob_component(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_component(&pattern[1..], name)
+                || (!name.is_empty() && glob_component(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_component(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_component(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+fn glob_path(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(&"**") => (0..=path.len()).any(|skip| glob_path(&pattern[1..], &path[skip..])),
+        Some(component) => {
+            !path.is_empty()
+                && glob_component(component.as_bytes(), path[0].as_bytes())
+                && glob_path(&pattern[1..], &path[1..])
+        }
+    }
+}
+
+pub fn matches(pattern: &str, relative: &Path) -> bool {
+    let pattern: Vec<&str> = pattern
+        .trim_matches('/')
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    let relative = relative.to_string_lossy();
+
+    let path: Vec<&str> = relative
+        .trim_matches('/')
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    glob_path(&pattern, &path)
+}
+
+fn encode_capabilities(names: &[String]) -> Result<Vec<u8>> {
+    let mut permitted: u64 = 0;
+
+    for name in names {
+        let normalized = name.trim().to_ascii_lowercase();
+
+        let normalized = normalized.trim_start_matches("cap_");
+
+        let bit = match CAPABILITY_NAMES.iter().position(|c| *c == normalized) {
+            Some(bit) => bit,
+            None => match normalized.parse::<usize>() {
+                Ok(bit) if bit < 64 => bit,
+                _ => bail!("unknown capability '{}'", name),
+            },
+        };
+
+        permitted |= 1 << bit;
+    }
+
+    let mut data = Vec::with_capacity(20);
+
+    data.extend_from_slice(&(VFS_CAP_REVISION_2 | VFS_CAP_FLAGS_EFFECTIVE).to_le_bytes());
+
+    data.extend_from_slice(&(permitted as u32).to_le_bytes());
+
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    data.extend_from_slice(&((permitted >> 32) as u32).to_le_bytes());
+
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    Ok(data)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+fn set_capabilities(path: &Path, names: &[String]) -> Result<()> {
+    let data = encode_capabilities(names)?;
+
+    lsetxattr(path, CAPABILITY_XATTR, &data, XattrFlags::empty())
+        .map_err(std::io::Error::from)
+        .with_context(|| format!("Failed to set capabilities on {}", path.display()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+fn set_capabilities(_path: &Path, names: &[String]) -> Result<()> {
+    encode_capabilities(names).map(|_| ())
+}
+
+impl AttrManifest {
+    pub fn load(module_root: &Path) -> Option<Self> {
+        let manifest_path = module_root.join(defs::ATTRS_FILE_NAME);
+
+        if !manifest_path.exists() {
+            return None;
+        }
+
+        match fs::read_to_string(&manifest_path) {
+            Ok(content) => match serde_json::from_str::<AttrManifest>(&content) {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    log::warn!("Failed to parse {}: {}", manifest_path.display(), e);
+
+                    None
+                }
+            },
+            Err(e) => {
+                log::warn!("Failed to read {}: {}", manifest_path.display(), e);
+
+                None
+            }
+        }
+    }
+
+    pub fn resolve(&self, relative: &Path) -> Option<ResolvedAttrs> {
+        let mut resolved = ResolvedAttrs::default();
+
+        let mut matched = false;
+
+        for rule in self.rules.iter().filter(|r| matches(&r.path, relative)) {
+            matched = true;
+
+            if rule.mode.is_some() {
+                resolved.mode = rule.mode;
+            }
+
+            if rule.uid.is_some() {
+                resolved.uid = rule.uid;
+            }
+
+            if rule.gid.is_some() {
+                resolved.gid = rule.gid;
+            }
+
+            if rule.context.is_some() {
+                resolved.context = rule.context.clone();
+            }
+
+            if rule.capabilities.is_some() {
+                resolved.capabilities = rule.capabilities.clone();
+            }
+        }
+
+        matched.then_some(resolved)
+    }
+
+    pub fn apply_tree(&self, root: &Path, scope: &str) -> AttrReport {
+        let mut report = AttrReport::default();
+
+        for entry in WalkDir::new(root).follow_links(false).into_iter().flatten() {
+            let Ok(relative) = entry.path().strip_prefix(root) else {
+                continue;
+            };
+
+            let Some(attrs) = self.resolve(relative) else {
+                continue;
+            };
+
+            match attrs.apply(entry.path()) {
+                Ok(_) => report.applied += 1,
+                Err(e) => report
+                    .failures
+                    .push((entry.path().to_path_buf(), format!("{:#}", e))),
+            }
+        }
+
+        report.log_summary(scope);
+
+        report
+    }
+}
+
+impl ResolvedAttrs {
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+
+        if self.uid.is_some() || self.gid.is_some() {
+            chownat(
+                CWD,
+                path,
+                self.uid.map(Uid::from_raw),
+                self.gid.map(Gid::from_raw),
+                AtFlags::SYMLINK_NOFOLLOW,
+            )
+            .with_context(|| format!("Failed to chown {}", path.display()))?;
+        }
+
+        if let Some(mode) = self.mode
+            && !metadata.file_type().is_symlink()
+        {
+            chmod(path, Mode::from_raw_mode(mode))
+                .with_context(|| format!("Failed to chmod {}", path.display()))?;
+        }
+
+        if let Some(context) = &self.context {
+            selinux::setfilecon_strict(path, context)?;
+        }
+
+        if let Some(capabilities) = &self.capabilities
+            && metadata.file_type().is_file()
+        {
+            set_capabilities(path, capabilities)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply_to_meta(&self, meta: &mut EntryMeta) -> Result<()> {
+        let file_type = FileType::from_raw_mode(meta.mode);
+
+        if let Some(mode) = self.mode
+            && file_type != FileType::Symlink
+        {
+            meta.mode = (meta.mode & !0o7777) | mode;
+        }
+
+        if let Some(uid) = self.uid {
+            meta.uid = uid;
+        }
+
+        if let Some(gid) = self.gid {
+            meta.gid = gid;
+        }
+
+        if let Some(context) = &self.context {
+            meta.xattrs.insert(
+                selinux::SELINUX_XATTR.to_string(),
+                context.as_bytes().to_vec(),
+            );
+        }
+
+        if let Some(capabilities) = &self.capabilities
+            && file_type == FileType::RegularFile
+        {
+            meta.xattrs.insert(
+                CAPABILITY_XATTR.to_string(),
+                encode_capabilities(capabilities)?,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl AttrReport {
+    pub fn log_summary(&self, scope: &str) {
+        if self.failures.is_empty() {
+            if self.applied > 0 {
+                log::debug!("Attributes {}: {} entries updated", scope, self.applied);
+            }
+
+            return;
+        }
+
+        log::warn!(
+            "Attributes {}: {} applied, {} failed",
+            scope,
+            self.applied,
+            self.failures.len()
+        );
+
+        for (path, error) in &self.failures {
+            log::debug!("   {} -> {}", path.display(), error);
+        }
+    }
+}
+
+impl AttrIndex {
+    pub fn load(module_paths: &[PathBuf]) -> Self {
+        let manifests = module_paths
+            .iter()
+            .filter_map(|root| AttrManifest::load(root).map(|m| (root.clone(), m)))
+            .collect();
+
+        Self { manifests }
+    }
+
+    pub fn resolve(&self, module_path: &Path) -> Option<ResolvedAttrs> {
+        self.manifests.iter().find_map(|(root, manifest)| {
+            let relative = module_path.strip_prefix(root).ok()?;
+
+            manifest.resolve(relative)
+        })
+    }
+
+    pub fn apply(&self, module_path: &Path, target: &Path) {
+        let Some(attrs) = self.resolve(module_path) else {
+            return;
+        };
+
+        if let Err(e) = attrs.apply(target) {
+            log::warn!("apply attributes to {}: {:#}", target.display(), e);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{fs, io::Read, path::Path, sync::OnceLock};
+
+use flate2::read::GzDecoder;
+use rustix::{
+    fd::{AsFd, AsRawFd},
+    fs::{CWD, Mode, OFlags, XattrFlags, fsetxattr, mkdirat, openat},
+    io::Errno,
+    mount::{
+        FsMountFlags, FsOpenFlags, MountAttrFlags, MoveMountFlags, OpenTreeFlags, fsconfig_create,
+        fsconfig_set_string, fsmount, fsopen, move_mount, open_tree,
+    },
+};
+use serde::Serialize;
+
+const OVERLAY_PARAMS_DIR: &str = "/sys/module/overlay/parameters";
+
+const EROFS_FEATURES_DIR: &str = "/sys/fs/erofs/features";
+
+const KERNEL_CONFIG_GZ: &str = "/proc/config.gz";
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+
+static CAPABILITIES: OnceLock<KernelCapabilities> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+
+pub enum Support {
+    Yes,
+    No,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+
+pub struct OverlayCapabilities {
+    pub supported: Support,
+    pub redirect_dir: bool,
+    pub metacopy: bool,
+    pub index: bool,
+    pub xino: bool,
+    pub lowerdir_append: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+
+pub struct ErofsCapabilities {
+    pub supported: bool,
+    pub compressors: Vec<String>,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+
+pub struct KernelCapabilities {
+    pub kernel_release: String,
+    pub new_mount_api: bool,
+    pub open_tree: bool,
+    pub detached_trees: bool,
+    pub overlay: OverlayCapabilities,
+    pub erofs: ErofsCapabilities,
+    pub loop_control: bool,
+    pub tmpfs_xattr: bool,
+    pub ksu_driver: bool,
+}
+
+impl KernelCapabilities {
+    pub fn log_summary(&self) {
+        log::debug!(
+            "Capabilities: new_mount_api={} open_tree={} detached_trees={} loop_control={} tmpfs_xattr={} ksu_driver={}",
+            self.new_mount_api,
+            self.open_tree,
+            self.detached_trees,
+            self.loop_control,
+            self.tmpfs_xattr,
+            self.ksu_driver
+        );
+
+        log::debug!(
+            "Capabilities: overlay={:?} (redirect_dir={} metacopy={} index={} xino={} lowerdir+={})",
+            self.overlay.supported,
+            self.overlay.redirect_dir,
+            self.overlay.metacopy,
+            self.overlay.index,
+            self.overlay.xino,
+            self.overlay.lowerdir_append
+        );
+
+        log::debug!(
+            "Capabilities: erofs={} compressors={:?}",
+            self.erofs.supported,
+            self.erofs.compressors
+        );
+    }
+}
+
+pub fn get() -> &'static KernelCapabilities {
+    CAPABILITIES.get_or_init(probe)
+}
+
+fn probe() -> KernelCapabilities {
+    let filesystems = fs::read_to_string("/proc/filesystems").ok();
+
+    let kernel_config = read_kernel_config();
+
+    let new_mount_api = probe_new_mount_api();
+
+    KernelCapabilities {
+        kernel_release: fs::read_to_string("/proc/sys/kernel/osrelease")
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default(),
+        new_mount_api,
+        open_tree: probe_open_tree(),
+        detached_trees: new_mount_api && probe_detached_trees(),
+        overlay: probe_overlay(
+            filesystems.as_deref(),
+            kernel_config.as_deref(),
+            new_mount_api,
+        ),
+        erofs: probe_erofs(
+            filesystems.as_deref().unwrap_or_default(),
+            kernel_config.as_deref(),
+        ),
+        loop_control: fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(LOOP_CONTROL)
+            .is_ok(),
+        tmpfs_xattr: new_mount_api && probe_tmpfs_xattr(),
+        ksu_driver: probe_ksu_driver(),
+    }
+}
+
+fn has_filesystem(filesystems: &str, name: &str) -> bool {
+    filesystems
+        .lines()
+        .any(|line| line.split_whitespace().last() == Some(name))
+}
+
+fn read_kernel_config() -> Option<String> {
+    let file = fs::File::open(KERNEL_CONFIG_GZ).ok()?;
+
+    let mut content = String::new();
+
+    GzDecoder::new(file).read_to_string(&mut content).ok()?;
+
+    Some(content)
+}
+
+fn probe_new_mount_api() -> bool {
+    match fsopen("tmpfs", FsOpenFlags::FSOPEN_CLOEXEC) {
+        Ok(_) => true,
+        Err(e) => {
+            log::debug!("Probe: fsopen unavailable: {}", e);
+
+            false
+        }
+    }
+}
+
+fn probe_open_tree() -> bool {
+    match open_tree(CWD, "/", OpenTreeFlags::OPEN_TREE_CLOEXEC) {
+        Ok(_) => true,
+        Err(e) => {
+            log::debug!("Probe: open_tree unavailable: {}", e);
+
+            false
+        }
+    }
+}
+
+fn probe_detached_trees() -> bool {
+    let result = (|| -> rustix::io::Result<()> {
+        let mut mounts = Vec::new();
+
+        for _ in 0..2 {
+            let fs = fsopen("tmpfs", FsOpenFlags::FSOPEN_CLOEXEC)?;
+
+            fsconfig_create(fs.as_fd())?;
+
+            mounts.push(fsmount(
+                fs.as_fd(),
+                FsMountFlags::FSMOUNT_CLOEXEC,
+                MountAttrFlags::empty(),
+            )?);
+        }
+
+        mkdirat(mounts[0].as_fd(), "child", Mode::from_raw_mode(0o755))?;
+
+        move_mount(
+            mounts[1].as_fd(),
+            "",
+            mounts[0].as_fd(),
+            "child",
+            MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+        )?;
+
+        open_tree(
+            CWD,
+            format!("/proc/self/fd/{}/child", mounts[0].as_raw_fd()),
+            OpenTreeFlags::OPEN_TREE_CLONE | OpenTreeFlags::OPEN_TREE_CLOEXEC,
+        )
+        .map(|_| ())
+    })();
+
+    if let Err(e) = &result {
+        log::debug!("Probe: mounting on detached trees unsupported: {}", e);
+    }
+
+    result.is_ok()
+}
+
+fn probe_overlay_support(
+    filesystems: Option<&str>,
+    kernel_config: Option<&str>,
+    new_mount_api: bool,
+) -> Support {
+    if filesystems.is_some_and(|f| has_filesystem(f, "overlay")) {
+        return Support::Yes;
+    }
+
+    if new_mount_api {
+        match fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC) {
+            Ok(_) => return Support::Yes,
+            Err(Errno::NODEV) => return Support::No,
+            Err(e) => log::debug!("Probe: fsopen overlay failed: {}", e),
+        }
+    }
+
+    let Some(config) = kernel_config else {
+        return Support::Unknown;
+    };
+
+    match config
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("CONFIG_OVERLAY_FS="))
+    {
+        Some("y") => Support::Yes,
+        Some(_) => Support::Unknown,
+        None => Support::No,
+    }
+}
+
+fn probe_overlay(
+    filesystems: Option<&str>,
+    kernel_config: Option<&str>,
+    new_mount_api: bool,
+) -> OverlayCapabilities {
+    let params = Path::new(OVERLAY_PARAMS_DIR);
+
+    let supported = probe_overlay_support(filesystems, kernel_config, new_mount_api);
+
+    OverlayCapabilities {
+        supported,
+        redirect_dir: params.join("redirect_dir").exists(),
+        metacopy: params.join("metacopy").exists(),
+        index: params.join("index").exists(),
+        xino: params.join("xino_auto").exists(),
+        lowerdir_append: supported != Support::No && new_mount_api && probe_lowerdir_append(),
+    }
+}
+
+fn probe_lowerdir_append() -> bool {
+    let result = (|| -> rustix::io::Result<()> {
+        let tmpfs = fsopen("tmpfs", FsOpenFlags::FSOPEN_CLOEXEC)?;
+
+        fsconfig_create(tmpfs.as_fd())?;
+
+        let scratch = fsmount(
+            tmpfs.as_fd(),
+            FsMountFlags::FSMOUNT_CLOEXEC,
+            MountAttrFlags::empty(),
+        )?;
+
+        let root = format!("/proc/self/fd/{}", scratch.as_raw_fd());
+
+        let overlay = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)?;
+
+        for layer in ["lower_a", "lower_b"] {
+            mkdirat(scratch.as_fd(), layer, Mode::from_raw_mode(0o755))?;
+
+            fsconfig_set_string(overlay.as_fd(), "lowerdir+", format!("{}/{}", root, layer))?;
+        }
+
+        fsconfig_set_string(overlay.as_fd(), "source", "probe")?;
+
+        fsconfig_create(overlay.as_fd())
+    })();
+
+    if let Err(e) = &result {
+        log::debug!("Probe: overlay lowerdir+ unsupported: {}", e);
+    }
+
+    result.is_ok()
+}
+
+fn probe_erofs(filesystems: &str, kernel_config: Option<&str>) -> ErofsCapabilities {
+    let supported = has_filesystem(filesystems, "erofs");
+
+    let mut features: Vec<String> = fs::read_dir(EROFS_FEATURES_DIR)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    features.sort();
+
+    let mut compressors = Vec::new();
+
+    if let Some(config) = kernel_config {
+        const OPTIONS: [(&str, &str); 4] = [
+            ("CONFIG_EROFS_FS_ZIP=y", "lz4"),
+            ("CONFIG_EROFS_FS_ZIP_LZMA=y", "lzma"),
+            ("CONFIG_EROFS_FS_ZIP_DEFLATE=y", "deflate"),
+            ("CONFIG_EROFS_FS_ZIP_ZSTD=y", "zstd"),
+        ];
+
+        for (option, name) in OPTIONS {
+            if config.lines().any(|line| line.trim() == option) {
+                compressors.push(name.to_string());
+            }
+        }
+    } else if supported
+        && features
+            .iter()
+            .any(|f| f == "zero_padding" || f == "lz4_0padding")
+    {
+        compressors.push("lz4".to_string());
+    }
+
+    ErofsCapabilities {
+        supported,
+        compressors,
+        features,
+    }
+}
+
+fn probe_tmpfs_xattr() -> bool {
+    let result = (|| -> rustix::io::Result<()> {
+        let fs = fsopen("tmpfs", FsOpenFlags::FSOPEN_CLOEXEC)?;
+
+        fsconfig_create(fs.as_fd())?;
+
+        let mnt = fsmount(
+            fs.as_fd(),
+            FsMountFlags::FSMOUNT_CLOEXEC,
+            MountAttrFlags::empty(),
+        )?;
+
+        let file = openat(
+            mnt.as_fd(),
+            ".xattr_probe",
+            OFlags::CREATE | OFlags::RDWR | OFlags::CLOEXEC,
+            Mode::from_raw_mode(0o600),
+        )?;
+
+        fsetxattr(file, "trusted.overlay.test", b"y", XattrFlags::empty())
+    })();
+
+    if let Err(e) = &result {
+        log::debug!("Probe: tmpfs trusted xattr unsupported: {}", e);
+    }
+
+    result.is_ok()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+fn probe_ksu_driver() -> bool {
+    crate::try_umount::is_driver_available()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+fn probe_ksu_driver() -> bool {
+    false
+}
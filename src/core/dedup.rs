@@ -0,0 +1,288 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+use crate::{core::state::DedupStats, erofs, utils};
+
+const MIN_SIZE: u64 = 4096;
+
+type InodeKey = (u64, u64);
+
+#[derive(PartialEq, Eq, Hash)]
+
+struct ContentKey {
+    size: u64,
+    digest: [u8; 32],
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+struct Inode {
+    size: u64,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+
+pub struct DedupOutcome {
+    pub stats: DedupStats,
+    pub relinked: HashSet<String>,
+}
+
+fn module_of(root: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(root)
+        .ok()?
+        .components()
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+}
+
+fn collect_inodes(root: &Path) -> HashMap<InodeKey, Inode> {
+    let mut inodes: HashMap<InodeKey, Inode> = HashMap::new();
+
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || e.file_name() != "lost+found")
+        .flatten()
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+
+        if meta.len() < MIN_SIZE {
+            continue;
+        }
+
+        inodes
+            .entry((meta.dev(), meta.ino()))
+            .or_insert_with(|| Inode {
+                size: meta.len(),
+                paths: Vec::new(),
+            })
+            .paths
+            .push(entry.into_path());
+    }
+
+    inodes
+}
+
+fn content_key(path: &Path, size: u64) -> Result<ContentKey> {
+    let meta = fs::symlink_metadata(path)?;
+
+    Ok(ContentKey {
+        size,
+        digest: utils::sha256_file(path)?,
+        mode: meta.mode(),
+        uid: meta.uid(),
+        gid: meta.gid(),
+        xattrs: erofs::read_xattrs(path),
+    })
+}
+
+fn relink(canonical: &Path, path: &Path) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let staging = path.with_file_name(format!(".{}.dedup", name));
+
+    let _ = fs::remove_file(&staging);
+
+    fs::hard_link(canonical, &staging)
+        .with_context(|| format!("Failed to link {}", path.display()))?;
+
+    fs::rename(&staging, path).inspect_err(|_| {
+        let _ = fs::remove_file(&staging);
+    })?;
+
+    Ok(())
+}
+
+fn unshare(paths: &[PathBuf]) -> Result<()> {
+    let first = &paths[0];
+
+    let name = first.file_name().unwrap_or_default().to_string_lossy();
+
+    let staging = first.with_file_name(format!(".{}.unshare", name));
+
+    utils::copy_entry(first, &staging)
+        .with_context(|| format!("Failed to copy {}", first.display()))?;
+
+    fs::rename(&staging, first).inspect_err(|_| {
+        let _ = fs::remove_file(&staging);
+    })?;
+
+    for path in &paths[1..] {
+        relink(first, path)?;
+    }
+
+    Ok(())
+}
+
+pub fn unshare_tree(module_root: &Path) -> usize {
+    let mut inodes: HashMap<InodeKey, (u64, Vec<PathBuf>)> = HashMap::new();
+
+    for entry in WalkDir::new(module_root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+
+        if meta.nlink() < 2 {
+            continue;
+        }
+
+        inodes
+            .entry((meta.dev(), meta.ino()))
+            .or_insert_with(|| (meta.nlink(), Vec::new()))
+            .1
+            .push(entry.into_path());
+    }
+
+    let mut unshared = 0;
+
+    for (nlink, paths) in inodes.into_values() {
+        if paths.len() as u64 >= nlink {
+            continue;
+        }
+
+        match unshare(&paths) {
+            Ok(_) => unshared += 1,
+            Err(e) => log::warn!("Dedup: failed to unshare: {:#}", e),
+        }
+    }
+
+    if unshared > 0 {
+        log::debug!(
+            "Dedup: unshared {} inodes in {}",
+            unshared,
+            module_root.display()
+        );
+    }
+
+    unshared
+}
+
+pub fn dedup_tree(root: &Path) -> DedupOutcome {
+    let mut inodes = collect_inodes(root);
+
+    let mut relinked = HashSet::new();
+
+    let mut by_size: HashMap<u64, Vec<InodeKey>> = HashMap::new();
+
+    for (key, inode) in &inodes {
+        by_size.entry(inode.size).or_default().push(*key);
+    }
+
+    let candidates: Vec<(InodeKey, &Path, u64)> = by_size
+        .values()
+        .filter(|keys| keys.len() > 1)
+        .flatten()
+        .map(|key| {
+            let inode = &inodes[key];
+
+            (*key, inode.paths[0].as_path(), inode.size)
+        })
+        .collect();
+
+    let keyed: Vec<(InodeKey, ContentKey)> = candidates
+        .par_iter()
+        .filter_map(|(key, path, size)| match content_key(path, *size) {
+            Ok(content) => Some((*key, content)),
+            Err(e) => {
+                log::debug!("Dedup: skipping {}: {:#}", path.display(), e);
+
+                None
+            }
+        })
+        .collect();
+
+    let mut groups: HashMap<ContentKey, Vec<InodeKey>> = HashMap::new();
+
+    for (key, content) in keyed {
+        groups.entry(content).or_default().push(key);
+    }
+
+    for mut group in groups.into_values().filter(|g| g.len() > 1) {
+        group.sort_by(|a, b| inodes[a].paths[0].cmp(&inodes[b].paths[0]));
+
+        let canonical_key = group[0];
+
+        let canonical = inodes[&canonical_key].paths[0].clone();
+
+        for duplicate in &group[1..] {
+            let Some(inode) = inodes.get_mut(duplicate) else {
+                continue;
+            };
+
+            let mut kept = Vec::new();
+
+            let mut linked = Vec::new();
+
+            for path in inode.paths.drain(..) {
+                match relink(&canonical, &path) {
+                    Ok(_) => {
+                        relinked.extend(module_of(root, &path));
+
+                        linked.push(path);
+                    }
+                    Err(e) => {
+                        log::warn!("Dedup: {:#}", e);
+
+                        kept.push(path);
+                    }
+                }
+            }
+
+            inode.paths = kept;
+
+            if let Some(target) = inodes.get_mut(&canonical_key) {
+                target.paths.extend(linked);
+            }
+        }
+    }
+
+    let mut stats = DedupStats::default();
+
+    for inode in inodes.values().filter(|i| i.paths.len() > 1) {
+        let extra = inode.paths.len() as u64 - 1;
+
+        stats.files += extra;
+
+        stats.bytes_saved += extra * inode.size;
+    }
+
+    if stats.files > 0 {
+        log::info!(
+            "Deduplicated storage: {} files share content, {} bytes saved",
+            stats.files,
+            stats.bytes_saved
+        );
+    }
+
+    DedupOutcome { stats, relinked }
+}
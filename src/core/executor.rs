@@ -4,7 +4,6 @@
 use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    process::Command,
 };
 
 use anyhow::Result;
@@ -13,20 +12,31 @@ use rustix::mount::UnmountFlags;
 use walkdir::WalkDir;
 
 use crate::{
-    conf::config,
-    core::planner::MountPlan,
+    conf::config::{self, PropagationMode},
+    core::{
+        planner::{MountPlan, OverlayOperation},
+        state::{self, PropagationRecord},
+    },
     defs,
-    mount::{magic, overlay},
-    utils,
+    mount::{
+        magic,
+        magic_preview::{self, MagicPreview},
+        mountinfo::MountTable,
+        overlay, propagation,
+    },
+    selinux, utils,
 };
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::try_umount::send_stacked_unmountable;
+
 pub struct ExecutionResult {
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub propagation: Vec<PropagationRecord>,
 }
 
 pub enum DiagnosticLevel {
-    #[allow(dead_code)]
     Info,
     Warning,
     Critical,
@@ -54,6 +64,64 @@ struct OverlayResult {
     success_records: Vec<(PathBuf, String)>,
 }
 
+struct MagicWork {
+    queue: Vec<PathBuf>,
+    exclusions: HashMap<PathBuf, HashSet<String>>,
+    fallback_ids: Vec<String>,
+}
+
+fn overlay_outcome(op: &OverlayOperation, mounted: bool) -> OverlayResult {
+    let mut result = OverlayResult {
+        magic_roots: Vec::new(),
+        fallback_ids: Vec::new(),
+        success_records: Vec::new(),
+    };
+
+    for layer_path in &op.lowerdirs {
+        let Some(root) = extract_module_root(layer_path) else {
+            continue;
+        };
+
+        if mounted {
+            result
+                .success_records
+                .push((root, op.partition_name.clone()));
+        } else {
+            result.magic_roots.push(root);
+
+            if let Some(id) = extract_id(layer_path) {
+                result.fallback_ids.push(id);
+            }
+        }
+    }
+
+    result
+}
+
+fn magic_work(plan: &MountPlan, overlay_results: Vec<OverlayResult>) -> MagicWork {
+    let mut work = MagicWork {
+        queue: plan.magic_module_paths.clone(),
+        exclusions: HashMap::new(),
+        fallback_ids: Vec::new(),
+    };
+
+    for res in overlay_results {
+        work.queue.extend(res.magic_roots);
+
+        work.fallback_ids.extend(res.fallback_ids);
+
+        for (root, partition) in res.success_records {
+            work.exclusions.entry(root).or_default().insert(partition);
+        }
+    }
+
+    work.queue.sort();
+
+    work.queue.dedup();
+
+    work
+}
+
 fn repair_rw_contexts() {
     let rw_root = Path::new(defs::SYSTEM_RW_DIR);
 
@@ -68,44 +136,228 @@ fn repair_rw_contexts() {
 
         let reference_path = Path::new("/").join(part);
 
-        if part_dir.exists() && reference_path.exists() {
-            let status = Command::new("chcon")
-                .arg("-R")
-                .arg("--reference")
-                .arg(&reference_path)
-                .arg(&part_dir)
-                .status();
-
-            match status {
-                Ok(s) if s.success() => {
-                    log::debug!(
-                        "Fixed context for {} using reference {}",
-                        part_dir.display(),
-                        reference_path.display()
-                    );
-                }
-                _ => {
-                    let context = "u:object_r:system_file:s0";
+        if !part_dir.exists() || !reference_path.exists() {
+            continue;
+        }
 
-                    log::warn!(
-                        "chcon --reference failed, trying explicit context {}",
-                        context
-                    );
+        let context = selinux::lgetfilecon(&reference_path).unwrap_or_else(|e| {
+            log::warn!(
+                "Failed to read reference context of {}: {:#}, using {}",
+                reference_path.display(),
+                e,
+                selinux::DEFAULT_CONTEXT
+            );
 
-                    let _ = Command::new("chcon")
-                        .arg("-R")
-                        .arg(context)
-                        .arg(&part_dir)
-                        .status();
-                }
-            }
+            selinux::DEFAULT_CONTEXT.to_string()
+        });
+
+        let report = selinux::relabel_tree(&part_dir, |_, _| Some(context.clone()));
+
+        report.log_summary(&part_dir.display().to_string());
+    }
+}
+
+fn propagation_targets(
+    plan: &MountPlan,
+    config: &config::Config,
+) -> Vec<(String, PathBuf, PropagationMode)> {
+    let mut targets: Vec<(String, PathBuf, PropagationMode)> = plan
+        .overlay_ops
+        .iter()
+        .map(|op| {
+            (
+                op.partition_name.clone(),
+                PathBuf::from(&op.target),
+                config.propagation_for(&op.partition_name),
+            )
+        })
+        .collect();
+
+    let partitions = defs::BUILTIN_PARTITIONS
+        .iter()
+        .map(|s| s.to_string())
+        .chain(config.partitions.iter().cloned());
+
+    for partition in partitions {
+        let target = Path::new("/").join(&partition);
+
+        if targets.iter().any(|(_, t, _)| *t == target) {
+            continue;
+        }
+
+        if plan
+            .magic_module_paths
+            .iter()
+            .any(|root| root.join(&partition).is_dir())
+        {
+            let mode = config.propagation_for(&partition);
+
+            targets.push((partition, target, mode));
         }
     }
+
+    targets
+}
+
+pub fn diagnose_propagation(plan: &MountPlan, config: &config::Config) -> Vec<DiagnosticIssue> {
+    let mut issues = Vec::new();
+
+    let records = state::RuntimeState::load()
+        .map(|state| state.propagation)
+        .unwrap_or_default();
+
+    for record in records {
+        let (level, message) = match &record.error {
+            Some(error) => (
+                DiagnosticLevel::Warning,
+                format!(
+                    "Propagation '{}' for {} failed at mount time: {}",
+                    record.mode,
+                    record.target.display(),
+                    error
+                ),
+            ),
+            None if record.applied => (
+                DiagnosticLevel::Info,
+                format!(
+                    "Propagation '{}' for {} applied at mount time",
+                    record.mode,
+                    record.target.display()
+                ),
+            ),
+            None => (
+                DiagnosticLevel::Info,
+                format!(
+                    "Propagation '{}' for {} not applied, nothing was mounted there",
+                    record.mode,
+                    record.target.display()
+                ),
+            ),
+        };
+
+        issues.push(DiagnosticIssue {
+            level,
+            context: record.partition,
+            message,
+        });
+    }
+
+    let Some(pid) = propagation::find_zygote_pid() else {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "propagation".to_string(),
+            message: "Zygote is not running, namespace visibility check skipped".to_string(),
+        });
+
+        return issues;
+    };
+
+    if propagation::shares_namespace(pid) {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Info,
+            context: "propagation".to_string(),
+            message: format!(
+                "Zygote (pid {}) shares our mount namespace, visibility check skipped",
+                pid
+            ),
+        });
+
+        return issues;
+    }
+
+    let tables = MountTable::read_self().and_then(|local| {
+        let remote = MountTable::read_pid(pid)?;
+
+        Ok((local, remote))
+    });
+
+    let (local, remote) = match tables {
+        Ok(tables) => tables,
+        Err(e) => {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: "propagation".to_string(),
+                message: format!("Unable to compare mount namespaces: {:#}", e),
+            });
+
+            return issues;
+        }
+    };
+
+    let targets = propagation_targets(plan, config);
+
+    for check in propagation::verify(&local, &remote, &targets) {
+        let state = if check.visible { "visible" } else { "hidden" };
+
+        let (level, message) = match (check.matches_expectation(), check.expect_visible()) {
+            (true, _) => (
+                DiagnosticLevel::Info,
+                format!(
+                    "{} ({}) is {} in zygote (pid {}) as expected for propagation '{}'",
+                    check.target.display(),
+                    check.local_fs_type,
+                    state,
+                    pid,
+                    check.mode.as_str()
+                ),
+            ),
+            (false, true) => (
+                DiagnosticLevel::Warning,
+                format!(
+                    "{} ({}) is not visible in zygote (pid {}), apps will not see module files",
+                    check.target.display(),
+                    check.local_fs_type,
+                    pid
+                ),
+            ),
+            (false, false) => (
+                DiagnosticLevel::Info,
+                format!(
+                    "{} is private but visible in zygote (pid {}), zygote was likely forked after mounting",
+                    check.target.display(),
+                    pid
+                ),
+            ),
+        };
+
+        issues.push(DiagnosticIssue {
+            level,
+            context: check.partition,
+            message,
+        });
+    }
+
+    issues
+}
+
+pub fn preview_magic(plan: &MountPlan, config: &config::Config) -> Result<MagicPreview> {
+    let outcomes = plan
+        .overlay_ops
+        .iter()
+        .map(|op| overlay_outcome(op, true))
+        .collect();
+
+    let work = magic_work(plan, outcomes);
+
+    magic_preview::preview_partitions(&work.queue, &config.partitions, &work.exclusions)
 }
 
 pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
     let mut issues = Vec::new();
 
+    let mount_table = match MountTable::read_self() {
+        Ok(table) => Some(table),
+        Err(e) => {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: "mountinfo".to_string(),
+                message: format!("Unable to read mount table: {:#}", e),
+            });
+
+            None
+        }
+    };
+
     for op in &plan.overlay_ops {
         let target = Path::new(&op.target);
 
@@ -116,6 +368,61 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
                 message: format!("Target mount point does not exist: {}", op.target),
             });
         }
+
+        let Some(table) = &mount_table else {
+            continue;
+        };
+
+        let stacked = table
+            .mounts_at(target)
+            .filter(|m| m.fs_type == "overlay" && m.source == defs::KSU_OVERLAY_SOURCE)
+            .count();
+
+        if stacked > 0 {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Warning,
+                context: op.partition_name.clone(),
+                message: format!(
+                    "{} already carries {} overlay mount(s) from a previous run",
+                    op.target, stacked
+                ),
+            });
+        }
+
+        if let Some(cover) = table.covering(target)
+            && cover.mount_point != target
+        {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Info,
+                context: op.partition_name.clone(),
+                message: format!(
+                    "{} is not a mount point, it lives on {} ({}) mounted at {}",
+                    op.target,
+                    cover.fs_type,
+                    cover.source,
+                    cover.mount_point.display()
+                ),
+            });
+        }
+
+        let sub_mounts = table.mounts_under(target);
+
+        if !sub_mounts.is_empty() {
+            issues.push(DiagnosticIssue {
+                level: DiagnosticLevel::Info,
+                context: op.partition_name.clone(),
+                message: format!(
+                    "{} sub-mount(s) under {} will be stashed and restored: {}",
+                    sub_mounts.len(),
+                    op.target,
+                    sub_mounts
+                        .iter()
+                        .map(|m| m.mount_point.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
     }
 
     let all_layers: Vec<(String, &PathBuf)> = plan
@@ -158,18 +465,46 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
 }
 
 pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionResult> {
-    let mut magic_queue = plan.magic_module_paths.clone();
-
-    let mut global_success_map: HashMap<PathBuf, HashSet<String>> = HashMap::new();
-
     let mut final_overlay_ids = HashSet::new();
 
     plan.overlay_module_ids.iter().for_each(|id| {
         final_overlay_ids.insert(id.clone());
     });
 
+    let snapshot_before = MountTable::read_self().ok();
+
     repair_rw_contexts();
 
+    let mut propagation_records: Vec<(PropagationMode, PropagationRecord)> =
+        propagation_targets(plan, config)
+            .into_iter()
+            .filter(|(_, _, mode)| *mode != PropagationMode::Inherit)
+            .map(|(partition, target, mode)| {
+                let result = propagation::prepare(&target, mode, snapshot_before.as_ref());
+
+                if let Err(e) = &result {
+                    log::warn!("Propagation for {} not prepared: {:#}", partition, e);
+                }
+
+                let record = PropagationRecord {
+                    partition,
+                    target,
+                    mode: mode.as_str().to_string(),
+                    applied: result.is_ok(),
+                    bound: result.as_ref().is_ok_and(|bound| *bound),
+                    error: result.err().map(|e| format!("{:#}", e)),
+                };
+
+                (mode, record)
+            })
+            .collect();
+
+    let prepared = propagation_records
+        .iter()
+        .any(|(_, record)| record.bound)
+        .then(|| MountTable::read_self().ok())
+        .flatten();
+
     log::info!(">> Phase 2: OverlayFS Execution...");
 
     let overlay_results: Vec<OverlayResult> = plan
@@ -202,75 +537,41 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
                 lowerdir_strings.len()
             );
 
-            if let Err(e) = overlay::mount_overlay(
+            let mounted = match overlay::mount_overlay(
                 &op.target,
                 &lowerdir_strings,
                 work_opt,
                 upper_opt,
+                op.method,
+                !plan.source_layers,
                 config.disable_umount,
             ) {
-                log::warn!(
-                    "OverlayFS failed for {}: {}. Triggering fallback.",
-                    op.target,
-                    e
-                );
-
-                let mut local_magic = Vec::new();
-
-                let mut local_fallback_ids = Vec::new();
-
-                for layer_path in &op.lowerdirs {
-                    if let Some(root) = extract_module_root(layer_path) {
-                        local_magic.push(root.clone());
-
-                        if let Some(id) = extract_id(layer_path) {
-                            local_fallback_ids.push(id);
-                        }
-                    }
-                }
-
-                return OverlayResult {
-                    magic_roots: local_magic,
-                    fallback_ids: local_fallback_ids,
-                    success_records: Vec::new(),
-                };
-            }
-
-            let mut successes = Vec::new();
+                Ok(_) => true,
+                Err(e) => {
+                    log::warn!(
+                        "OverlayFS failed for {}: {}. Triggering fallback.",
+                        op.target,
+                        e
+                    );
 
-            for layer_path in &op.lowerdirs {
-                if let Some(root) = extract_module_root(layer_path) {
-                    successes.push((root, op.partition_name.clone()));
+                    false
                 }
-            }
+            };
 
-            OverlayResult {
-                magic_roots: Vec::new(),
-                fallback_ids: Vec::new(),
-                success_records: successes,
-            }
+            overlay_outcome(op, mounted)
         })
         .collect();
 
-    for res in overlay_results {
-        magic_queue.extend(res.magic_roots);
+    let MagicWork {
+        queue: magic_queue,
+        exclusions: global_success_map,
+        fallback_ids,
+    } = magic_work(plan, overlay_results);
 
-        for id in res.fallback_ids {
-            final_overlay_ids.remove(&id);
-        }
-
-        for (root, partition) in res.success_records {
-            global_success_map
-                .entry(root)
-                .or_default()
-                .insert(partition);
-        }
+    for id in fallback_ids {
+        final_overlay_ids.remove(&id);
     }
 
-    magic_queue.sort();
-
-    magic_queue.dedup();
-
     let mut final_magic_ids = Vec::new();
 
     if !magic_queue.is_empty() {
@@ -291,7 +592,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             std::fs::create_dir_all(&tempdir)?;
         }
 
-        utils::mount_tmpfs(&tempdir, "tmpfs")?;
+        utils::mount_tmpfs(&tempdir, "tmpfs", None)?;
 
         if let Err(e) = magic::mount_partitions(
             &tempdir,
@@ -299,6 +600,7 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
             &config.mountsource,
             &config.partitions,
             global_success_map,
+            &config.propagation,
             config.disable_umount,
         ) {
             log::error!("Magic Mount critical failure: {:#}", e);
@@ -309,6 +611,86 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
         let _ = rustix::mount::unmount(&tempdir, UnmountFlags::DETACH);
     }
 
+    let mounted = MountTable::read_self().ok();
+
+    let module_mounts = prepared
+        .as_ref()
+        .zip(mounted.as_ref())
+        .map(|(before, after)| before.diff(after).added);
+
+    for (mode, record) in &mut propagation_records {
+        if record.bound {
+            let covered = module_mounts.as_ref().is_none_or(|added| {
+                added
+                    .iter()
+                    .any(|e| e.mount_point.starts_with(&record.target))
+            });
+
+            if !covered {
+                log::debug!(
+                    "Nothing mounted on {}, releasing its self-bind",
+                    record.target.display()
+                );
+
+                if let Err(e) = propagation::release(&record.target) {
+                    log::warn!("{:#}", e);
+                }
+
+                record.applied = false;
+
+                record.bound = false;
+
+                continue;
+            }
+
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            if !config.disable_umount {
+                let _ = send_stacked_unmountable(&record.target);
+            }
+        }
+
+        if !record.applied
+            || !mounted
+                .as_ref()
+                .is_some_and(|t| t.is_mount_point(&record.target))
+        {
+            continue;
+        }
+
+        if let Err(e) = propagation::apply(&record.target, *mode) {
+            log::warn!("Propagation for {} not applied: {:#}", record.partition, e);
+
+            record.applied = false;
+
+            record.error = Some(format!("{:#}", e));
+        }
+    }
+
+    if let Some(before) = snapshot_before
+        && let Some(after) = mounted
+    {
+        let delta = before.diff(&after);
+
+        if delta.is_empty() {
+            log::debug!(">> Mount table unchanged after execution.");
+        } else {
+            log::info!(
+                ">> Mount table delta: +{} / -{}",
+                delta.added.len(),
+                delta.removed.len()
+            );
+        }
+
+        for entry in &delta.added {
+            log::debug!(
+                "   + {} ({} from {})",
+                entry.mount_point.display(),
+                entry.fs_type,
+                entry.source
+            );
+        }
+    }
+
     let mut result_overlay = final_overlay_ids.into_iter().collect::<Vec<_>>();
 
     let mut result_magic = final_magic_ids;
@@ -322,5 +704,9 @@ pub fn execute(plan: &MountPlan, config: &config::Config) -> Result<ExecutionRes
     Ok(ExecutionResult {
         overlay_module_ids: result_overlay,
         magic_module_ids: result_magic,
+        propagation: propagation_records
+            .into_iter()
+            .map(|(_, record)| record)
+            .collect(),
     })
 }
@@ -2,16 +2,26 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
-    io::Write,
-    path::Path,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
 use serde::{Deserialize, Serialize};
-
-use crate::{conf::config::Config, defs};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conf::config::Config,
+    core::{
+        granary_store::{self, ObjectStore},
+        inventory, modules,
+    },
+    defs, utils,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 
@@ -25,6 +35,69 @@ pub struct Silo {
     pub raw_config: Option<String>,
     #[serde(default)]
     pub raw_state: Option<String>,
+    #[serde(default)]
+    pub modules: Option<Vec<ModuleFlags>>,
+    #[serde(default)]
+    pub rules: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub module_trees: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub module_versions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+
+pub enum ModuleRestore {
+    Skip,
+    All,
+    Only(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+
+pub struct ModuleFlags {
+    pub id: String,
+    #[serde(default)]
+    pub disable: bool,
+    #[serde(default)]
+    pub remove: bool,
+    #[serde(default)]
+    pub skip_mount: bool,
+}
+
+impl ModuleFlags {
+    fn read(id: String, dir: &Path) -> Self {
+        Self {
+            disable: dir.join(defs::DISABLE_FILE_NAME).exists(),
+            remove: dir.join(defs::REMOVE_FILE_NAME).exists(),
+            skip_mount: dir.join(defs::SKIP_MOUNT_FILE_NAME).exists(),
+            id,
+        }
+    }
+
+    fn files(&self) -> [(&'static str, bool); 3] {
+        [
+            (defs::DISABLE_FILE_NAME, self.disable),
+            (defs::REMOVE_FILE_NAME, self.remove),
+            (defs::SKIP_MOUNT_FILE_NAME, self.skip_mount),
+        ]
+    }
+}
+
+struct Swap {
+    staging: PathBuf,
+    target: PathBuf,
+    backup: Option<PathBuf>,
+}
+
+#[derive(Default)]
+
+struct Restore {
+    staged: Vec<(PathBuf, PathBuf)>,
+    swapped: Vec<Swap>,
+    flags: Vec<(PathBuf, bool)>,
+    flipped: usize,
+    restored: HashSet<String>,
 }
 
 const RATOON_COUNTER_FILE: &str = "/data/adb/meta-hybrid/ratoon_counter";
@@ -37,6 +110,337 @@ const CONFIG_PATH: &str = "/data/adb/meta-hybrid/config.toml";
 
 const STATE_PATH: &str = "/data/adb/meta-hybrid/state.json";
 
+const ARCHIVE_MAGIC: &str = "META-HYBRID-SILO";
+
+const ARCHIVE_VERSION: u32 = 1;
+
+const ARCHIVE_EXTENSION: &str = "mhsilo";
+
+const MAX_HEADER_LEN: usize = 256;
+
+const MAX_SILO_SIZE: u64 = 16 * 1024 * 1024;
+
+fn object_store() -> ObjectStore {
+    ObjectStore::new(Path::new(GRANARY_DIR).join("objects"))
+}
+
+fn staging_dir() -> PathBuf {
+    Path::new(GRANARY_DIR).join("staging")
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{}.{}", name, suffix))
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+fn set_flag(path: &Path, present: bool) -> Result<()> {
+    if present {
+        fs::File::create(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+impl Restore {
+    fn stage_file(&mut self, target: &Path, content: &str) -> Result<()> {
+        let staging = sibling(target, "restore");
+
+        self.staged.push((staging.clone(), target.to_path_buf()));
+
+        fs::write(&staging, content)
+            .with_context(|| format!("Failed to stage {}", target.display()))
+    }
+
+    fn stage_rules(&mut self, rules: &BTreeMap<String, String>) -> Result<()> {
+        let target = Path::new(defs::RULES_DIR);
+
+        let staging = sibling(target, "restore");
+
+        remove_path(&staging)?;
+
+        self.staged.push((staging.clone(), target.to_path_buf()));
+
+        fs::create_dir_all(&staging)?;
+
+        for (name, content) in rules {
+            if name.contains('/') || name.starts_with('.') || !name.ends_with(".json") {
+                bail!("Invalid rules file name in silo: {}", name);
+            }
+
+            fs::write(staging.join(name), content)
+                .with_context(|| format!("Failed to stage rules file {}", name))?;
+        }
+
+        Ok(())
+    }
+
+    fn stage_modules(&mut self, silo: &Silo, selection: &ModuleRestore) -> Result<()> {
+        let ids: Vec<String> = match selection {
+            ModuleRestore::Skip => return Ok(()),
+            ModuleRestore::All => silo.module_trees.keys().cloned().collect(),
+            ModuleRestore::Only(ids) => ids.clone(),
+        };
+
+        if ids.is_empty() {
+            log::warn!(">> This Silo holds no module snapshots. Skipping module restore.");
+
+            return Ok(());
+        }
+
+        let store = object_store();
+
+        for id in ids {
+            utils::validate_module_id(&id)?;
+
+            let Some(tree) = silo.module_trees.get(&id) else {
+                bail!("Silo {} has no snapshot of module {}", silo.id, id);
+            };
+
+            log::info!(">> Restoring module {} from snapshot...", id);
+
+            let staging = staging_dir().join(&id);
+
+            self.staged
+                .push((staging.clone(), Path::new(defs::MODULES_DIR).join(&id)));
+
+            granary_store::restore_module(&store, tree, &staging)
+                .with_context(|| format!("Failed to stage module {}", id))?;
+
+            self.restored.insert(id);
+        }
+
+        Ok(())
+    }
+
+    fn stage_flags(&mut self, modules: &[ModuleFlags]) {
+        for module in modules {
+            let dir = Path::new(defs::MODULES_DIR).join(&module.id);
+
+            let restored = self.restored.contains(&module.id);
+
+            if !restored && !dir.is_dir() {
+                log::warn!(
+                    ">> Module {} is no longer installed, skipping its flags",
+                    module.id
+                );
+
+                continue;
+            }
+
+            for (name, present) in module.files() {
+                let path = dir.join(name);
+
+                let exists = !restored && path.exists();
+
+                if exists != present {
+                    self.flags.push((path, present));
+                }
+            }
+        }
+    }
+
+    fn prepare(&mut self, silo: &Silo, modules: &ModuleRestore) -> Result<()> {
+        match &silo.raw_config {
+            Some(raw) => {
+                log::info!(">> Restoring config from RAW content (preserving comments)...");
+
+                self.stage_file(Path::new(CONFIG_PATH), raw)?;
+            }
+            None => {
+                log::info!(">> Raw config missing, restoring from struct snapshot...");
+
+                let toml_str = toml::to_string(&silo.config_snapshot)?;
+
+                self.stage_file(Path::new(CONFIG_PATH), &toml_str)?;
+            }
+        }
+
+        if let Some(state) = &silo.raw_state {
+            log::info!(">> Restoring state from snapshot...");
+
+            self.stage_file(Path::new(STATE_PATH), state)?;
+        } else {
+            log::warn!(">> No state snapshot found in this Silo. Skipping state restore.");
+        }
+
+        if let Some(rules) = &silo.rules {
+            log::info!(">> Restoring {} module rule file(s)...", rules.len());
+
+            self.stage_rules(rules)?;
+        } else {
+            log::warn!(">> No rules snapshot found in this Silo. Skipping rules restore.");
+        }
+
+        self.stage_modules(silo, modules)?;
+
+        if let Some(flags) = &silo.modules {
+            log::info!(">> Restoring enable state of {} module(s)...", flags.len());
+
+            self.stage_flags(flags);
+        } else {
+            log::warn!(">> No module state found in this Silo. Skipping module flags.");
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self) -> Result<()> {
+        for (staging, target) in self.staged.clone() {
+            let backup = if fs::symlink_metadata(&target).is_ok() {
+                let backup = sibling(&staging, "old");
+
+                remove_path(&backup)?;
+
+                fs::rename(&target, &backup)
+                    .with_context(|| format!("Failed to move aside {}", target.display()))?;
+
+                Some(backup)
+            } else {
+                None
+            };
+
+            self.swapped.push(Swap {
+                staging: staging.clone(),
+                target: target.clone(),
+                backup,
+            });
+
+            fs::rename(&staging, &target)
+                .with_context(|| format!("Failed to replace {}", target.display()))?;
+        }
+
+        for (path, present) in &self.flags {
+            set_flag(path, *present)
+                .with_context(|| format!("Failed to update {}", path.display()))?;
+
+            self.flipped += 1;
+        }
+
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        for (path, present) in self.flags.iter().take(self.flipped) {
+            if let Err(e) = set_flag(path, !present) {
+                log::error!("Failed to revert {}: {}", path.display(), e);
+            }
+        }
+
+        for swap in self.swapped.drain(..).rev() {
+            if fs::symlink_metadata(&swap.staging).is_err()
+                && let Err(e) = remove_path(&swap.target)
+            {
+                log::error!("Failed to discard {}: {}", swap.target.display(), e);
+            }
+
+            if let Some(backup) = &swap.backup
+                && let Err(e) = fs::rename(backup, &swap.target)
+            {
+                log::error!("Failed to put back {}: {}", swap.target.display(), e);
+            }
+
+            let _ = remove_path(&swap.staging);
+        }
+
+        self.discard();
+    }
+
+    fn discard(&mut self) {
+        for (staging, _) in self.staged.drain(..) {
+            let _ = remove_path(&staging);
+        }
+    }
+
+    fn commit(mut self) -> Result<()> {
+        if let Err(e) = self.apply() {
+            log::error!(">> Restore failed, rolling back: {:#}", e);
+
+            self.rollback();
+
+            return Err(e);
+        }
+
+        for swap in &self.swapped {
+            if let Some(backup) = &swap.backup
+                && let Err(e) = remove_path(backup)
+            {
+                log::warn!("Failed to remove {}: {}", backup.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn capture_module_flags() -> Result<Vec<ModuleFlags>> {
+    let modules_dir = Path::new(defs::MODULES_DIR);
+
+    let mut modules = Vec::new();
+
+    if !modules_dir.exists() {
+        return Ok(modules);
+    }
+
+    for entry in fs::read_dir(modules_dir)? {
+        let entry = entry?;
+
+        let path = entry.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        modules.push(ModuleFlags::read(
+            entry.file_name().to_string_lossy().to_string(),
+            &path,
+        ));
+    }
+
+    modules.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(modules)
+}
+
+fn capture_rules() -> Result<BTreeMap<String, String>> {
+    let rules_dir = Path::new(defs::RULES_DIR);
+
+    let mut rules = BTreeMap::new();
+
+    if !rules_dir.exists() {
+        return Ok(rules);
+    }
+
+    for entry in fs::read_dir(rules_dir)? {
+        let entry = entry?;
+
+        let path = entry.path();
+
+        if !path.is_file() || path.extension().and_then(|s| s.to_str()) != Some("json") {
+            continue;
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        rules.insert(entry.file_name().to_string_lossy().to_string(), content);
+    }
+
+    Ok(rules)
+}
+
 pub fn engage_ratoon_protocol() -> Result<()> {
     let path = Path::new(RATOON_COUNTER_FILE);
 
@@ -114,6 +518,14 @@ pub fn disengage_ratoon_protocol() {
 }
 
 pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String> {
+    store_silo(config, label, reason, false)
+}
+
+pub fn create_boot_silo(config: &Config) -> Result<String> {
+    store_silo(config, "Boot Backup", "Automatic Pre-Mount", true)
+}
+
+fn store_silo(config: &Config, label: &str, reason: &str, boot: bool) -> Result<String> {
     if let Err(e) = fs::create_dir_all(GRANARY_DIR) {
         log::warn!("Failed to create granary dir: {}", e);
     }
@@ -126,6 +538,20 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
 
     let raw_state = fs::read_to_string(STATE_PATH).ok();
 
+    let modules = capture_module_flags()
+        .inspect_err(|e| log::warn!("Granary: Failed to capture module flags: {:#}", e))
+        .ok();
+
+    let rules = capture_rules()
+        .inspect_err(|e| log::warn!("Granary: Failed to capture module rules: {:#}", e))
+        .ok();
+
+    let (module_trees, module_versions) = if config.granary.snapshot_modules {
+        snapshot_modules(boot)
+    } else {
+        Default::default()
+    };
+
     let silo = Silo {
         id: id.clone(),
         timestamp: now,
@@ -134,13 +560,13 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
         config_snapshot: config.clone(),
         raw_config,
         raw_state,
+        modules,
+        rules,
+        module_trees,
+        module_versions,
     };
 
-    let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
-
-    let json = serde_json::to_string_pretty(&silo)?;
-
-    fs::write(&file_path, json)?;
+    write_silo(&silo)?;
 
     if let Err(e) = prune_silos(config) {
         log::warn!("Failed to prune granary: {}", e);
@@ -183,13 +609,17 @@ pub fn delete_silo(id: &str) -> Result<()> {
 
         log::info!("Deleted Silo: {}", id);
 
+        if let Err(e) = collect_garbage() {
+            log::warn!("Failed to clean granary objects: {:#}", e);
+        }
+
         Ok(())
     } else {
         bail!("Silo {} not found", id);
     }
 }
 
-pub fn restore_silo(id: &str) -> Result<()> {
+pub fn load_silo(id: &str) -> Result<Silo> {
     let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
 
     if !file_path.exists() {
@@ -198,38 +628,208 @@ pub fn restore_silo(id: &str) -> Result<()> {
 
     let content = fs::read_to_string(&file_path)?;
 
-    let silo: Silo = serde_json::from_str(&content)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn restore_silo(id: &str, modules: &ModuleRestore) -> Result<()> {
+    let silo = load_silo(id)?;
 
     log::info!(">> Restoring Silo: {} ({})", silo.id, silo.label);
 
-    if let Some(raw) = &silo.raw_config {
-        log::info!(">> Restoring config from RAW content (preserving comments)...");
+    let mut restore = Restore::default();
 
-        fs::write(CONFIG_PATH, raw)?;
-    } else {
-        log::info!(">> Raw config missing, restoring from struct snapshot...");
+    if let Err(e) = restore.prepare(&silo, modules) {
+        restore.discard();
+
+        return Err(e.context(format!("Failed to prepare restore of Silo {}", silo.id)));
+    }
+
+    restore.commit()
+}
+
+pub fn export_silo(id: &str, output: Option<&Path>) -> Result<PathBuf> {
+    let mut silo = load_silo(id)?;
 
-        let toml_str = toml::to_string(&silo.config_snapshot)?;
+    if !silo.module_trees.is_empty() {
+        log::info!("Module snapshots of {} are not included in the archive", id);
 
-        fs::write(CONFIG_PATH, toml_str)?;
+        silo.module_trees.clear();
+
+        silo.module_versions.clear();
     }
 
-    if let Some(state) = &silo.raw_state {
-        log::info!(">> Restoring state from snapshot...");
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
 
-        fs::write(STATE_PATH, state)?;
-    } else {
-        log::warn!(">> No state snapshot found in this Silo. Skipping state restore.");
+    encoder.write_all(&serde_json::to_vec(&silo)?)?;
+
+    let payload = encoder.finish()?;
+
+    let header = format!(
+        "{} {} {} {}\n",
+        ARCHIVE_MAGIC,
+        ARCHIVE_VERSION,
+        granary_store::hex(&Sha256::digest(&payload)),
+        payload.len()
+    );
+
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => Path::new(GRANARY_DIR)
+            .join("exports")
+            .join(format!("{}.{}", id, ARCHIVE_EXTENSION)),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let staging = sibling(&path, "tmp");
+
+    let mut file = fs::File::create(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+
+    file.write_all(header.as_bytes())?;
+
+    file.write_all(&payload)?;
+
+    file.sync_all()?;
+
+    fs::rename(&staging, &path)?;
+
+    log::info!("Exported Silo {} to {}", id, path.display());
+
+    Ok(path)
+}
+
+fn read_archive(path: &Path) -> Result<Silo> {
+    if fs::metadata(path)?.len() > MAX_SILO_SIZE + MAX_HEADER_LEN as u64 {
+        bail!("archive is larger than {} bytes", MAX_SILO_SIZE);
+    }
+
+    let data = fs::read(path)?;
+
+    let Some(split) = data.iter().take(MAX_HEADER_LEN).position(|b| *b == b'\n') else {
+        bail!("not a silo archive");
+    };
+
+    let header = std::str::from_utf8(&data[..split]).context("invalid archive header")?;
+
+    let [magic, version, checksum, length] = header.split(' ').collect::<Vec<_>>()[..] else {
+        bail!("malformed archive header");
+    };
+
+    if magic != ARCHIVE_MAGIC {
+        bail!("not a silo archive");
+    }
+
+    let version: u32 = version.parse().context("invalid archive version")?;
+
+    if version != ARCHIVE_VERSION {
+        bail!("unsupported archive version {}", version);
+    }
+
+    let payload = &data[split + 1..];
+
+    let length: usize = length.parse().context("invalid archive length")?;
+
+    if payload.len() != length {
+        bail!(
+            "archive is truncated: expected {} bytes, found {}",
+            length,
+            payload.len()
+        );
+    }
+
+    if granary_store::hex(&Sha256::digest(payload)) != checksum {
+        bail!("archive checksum mismatch");
+    }
+
+    let mut json = Vec::new();
+
+    GzDecoder::new(payload)
+        .take(MAX_SILO_SIZE + 1)
+        .read_to_end(&mut json)
+        .context("failed to decompress archive")?;
+
+    if json.len() as u64 > MAX_SILO_SIZE {
+        bail!("archive expands beyond {} bytes", MAX_SILO_SIZE);
+    }
+
+    serde_json::from_slice(&json).context("archive does not match the current silo schema")
+}
+
+fn validate_silo(silo: &Silo) -> Result<()> {
+    if let Some(raw) = &silo.raw_config {
+        toml::from_str::<Config>(raw)
+            .context("archived config.toml does not match the current config schema")?;
+    }
+
+    if let Some(rules) = &silo.rules {
+        for (name, content) in rules {
+            let Some(module) = name.strip_suffix(".json") else {
+                bail!("invalid rules file name {}", name);
+            };
+
+            utils::validate_module_id(module)?;
+
+            serde_json::from_str::<inventory::ModuleRules>(content)
+                .with_context(|| format!("invalid rules for module {}", module))?;
+        }
+    }
+
+    for module in silo.modules.iter().flatten() {
+        utils::validate_module_id(&module.id)?;
     }
 
     Ok(())
 }
 
+pub fn import_silo(path: &Path, config: &Config) -> Result<String> {
+    let mut silo =
+        read_archive(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    validate_silo(&silo).with_context(|| format!("Rejected archive {}", path.display()))?;
+
+    fs::create_dir_all(GRANARY_DIR)?;
+
+    let mut now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    while Path::new(GRANARY_DIR)
+        .join(format!("silo_{}.json", now))
+        .exists()
+    {
+        now += 1;
+    }
+
+    log::info!(
+        "Importing Silo {} ({}) as silo_{}",
+        silo.id,
+        silo.label,
+        now
+    );
+
+    silo.id = format!("silo_{}", now);
+
+    silo.timestamp = now;
+
+    silo.module_trees.clear();
+
+    silo.module_versions.clear();
+
+    write_silo(&silo)?;
+
+    if let Err(e) = prune_silos(config) {
+        log::warn!("Failed to prune granary: {}", e);
+    }
+
+    Ok(silo.id)
+}
+
 fn restore_latest_silo() -> Result<String> {
     let silos = list_silos()?;
 
     if let Some(latest) = silos.first() {
-        restore_silo(&latest.id)?;
+        restore_silo(&latest.id, &ModuleRestore::Skip)?;
 
         Ok(latest.id.clone())
     } else {
@@ -238,6 +838,8 @@ fn restore_latest_silo() -> Result<String> {
 }
 
 fn prune_silos(config: &Config) -> Result<()> {
+    let mut stripped = prune_module_trees(config)?;
+
     let silos = list_silos()?;
 
     let max_count = config.granary.max_backups;
@@ -254,14 +856,14 @@ fn prune_silos(config: &Config) -> Result<()> {
         0
     };
 
-    for (i, silo) in silos.iter().enumerate() {
+    for (position, silo) in silos.iter().enumerate() {
         let mut should_delete = false;
 
-        if max_count > 0 && i >= max_count {
+        if max_count > 0 && position >= max_count {
             should_delete = true;
         }
 
-        if retention_days > 0 && silo.timestamp < expiration_ts && i > 0 {
+        if retention_days > 0 && silo.timestamp < expiration_ts && position > 0 {
             should_delete = true;
         }
 
@@ -272,6 +874,8 @@ fn prune_silos(config: &Config) -> Result<()> {
                 log::warn!("Failed to delete old silo {}: {}", silo.id, e);
             } else {
                 deleted_count += 1;
+
+                stripped |= !silo.module_trees.is_empty();
             }
         }
     }
@@ -280,6 +884,180 @@ fn prune_silos(config: &Config) -> Result<()> {
         log::info!("Granary Prune: Deleted {} old snapshots.", deleted_count);
     }
 
+    if stripped {
+        collect_garbage()?;
+    }
+
+    Ok(())
+}
+
+fn snapshot_modules(changed_only: bool) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let store = object_store();
+
+    let mut latest: BTreeMap<String, (String, Option<String>)> = BTreeMap::new();
+
+    for silo in list_silos().unwrap_or_default() {
+        for (module, tree) in silo.module_trees {
+            let version = silo.module_versions.get(&module).cloned();
+
+            latest.entry(module).or_insert((tree, version));
+        }
+    }
+
+    let mut trees = BTreeMap::new();
+
+    let mut versions = BTreeMap::new();
+
+    let Ok(entries) = fs::read_dir(defs::MODULES_DIR) else {
+        return (trees, versions);
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let id = entry.file_name().to_string_lossy().to_string();
+
+        if !path.is_dir() || id == "meta-hybrid" || utils::validate_module_id(&id).is_err() {
+            continue;
+        }
+
+        let version = modules::read_version(&path);
+
+        let previous = latest.get(&id);
+
+        if changed_only && previous.is_some_and(|(_, v)| v.as_deref() == Some(version.as_str())) {
+            continue;
+        }
+
+        let cached = previous.and_then(|(tree, _)| store.load_tree(tree).ok());
+
+        match granary_store::snapshot_module(&store, &path, cached.as_ref()) {
+            Ok(tree) => {
+                trees.insert(id.clone(), tree);
+
+                versions.insert(id, version);
+            }
+            Err(e) => log::warn!("Granary: Failed to snapshot module {}: {:#}", id, e),
+        }
+    }
+
+    if changed_only && trees.is_empty() {
+        log::debug!("Granary: No module versions changed since the last snapshot.");
+    } else {
+        log::info!("Granary: Snapshotted {} module(s).", trees.len());
+    }
+
+    (trees, versions)
+}
+
+fn write_silo(silo: &Silo) -> Result<()> {
+    let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", silo.id));
+
+    let staging = file_path.with_extension("json.tmp");
+
+    fs::write(&staging, serde_json::to_string_pretty(silo)?)?;
+
+    fs::rename(&staging, &file_path)?;
+
+    Ok(())
+}
+
+fn prune_module_trees(config: &Config) -> Result<bool> {
+    let keep = config.granary.module_snapshots;
+
+    if keep == 0 {
+        return Ok(false);
+    }
+
+    let mut kept: HashMap<String, Vec<String>> = HashMap::new();
+
+    let mut stripped = 0;
+
+    for mut silo in list_silos()?
+        .into_iter()
+        .filter(|s| !s.module_trees.is_empty())
+    {
+        let before = silo.module_trees.len();
+
+        silo.module_trees.retain(|module, _| {
+            let version = silo
+                .module_versions
+                .get(module)
+                .cloned()
+                .unwrap_or_default();
+
+            let versions = kept.entry(module.clone()).or_default();
+
+            if (!version.is_empty() && versions.contains(&version)) || versions.len() >= keep {
+                return false;
+            }
+
+            versions.push(version);
+
+            true
+        });
+
+        if silo.module_trees.len() == before {
+            continue;
+        }
+
+        let trees = &silo.module_trees;
+
+        silo.module_versions
+            .retain(|module, _| trees.contains_key(module));
+
+        match write_silo(&silo) {
+            Ok(_) => stripped += 1,
+            Err(e) => log::warn!("Failed to drop module snapshots of {}: {}", silo.id, e),
+        }
+    }
+
+    if stripped > 0 {
+        log::info!(
+            "Granary Prune: Dropped superseded module snapshots from {} silos.",
+            stripped
+        );
+    }
+
+    Ok(stripped > 0)
+}
+
+fn collect_garbage() -> Result<()> {
+    let store = object_store();
+
+    let silos = list_silos()?;
+
+    let on_disk = fs::read_dir(GRANARY_DIR)?
+        .flatten()
+        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("json"))
+        .count();
+
+    if on_disk != silos.len() {
+        bail!("{} silo files could not be parsed", on_disk - silos.len());
+    }
+
+    let mut live = HashSet::new();
+
+    for silo in silos {
+        for (module, tree) in &silo.module_trees {
+            let objects = store.tree_objects(tree).with_context(|| {
+                format!("Silo {} references unreadable tree of {}", silo.id, module)
+            })?;
+
+            live.extend(objects);
+        }
+    }
+
+    let (removed, freed) = store.gc(&live)?;
+
+    if removed > 0 {
+        log::info!(
+            "Granary GC: Removed {} unreferenced objects ({} bytes).",
+            removed,
+            freed
+        );
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,240 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        granary::{self, ModuleFlags, Silo},
+        inventory,
+    },
+};
+
+#[derive(Debug, Serialize)]
+
+pub struct Change<T> {
+    pub key: String,
+    pub before: Option<T>,
+    pub after: Option<T>,
+}
+
+#[derive(Debug, Serialize)]
+
+pub struct ModuleRulesDiff {
+    pub module: String,
+    pub changes: Vec<Change<Value>>,
+}
+
+#[derive(Debug, Serialize)]
+
+pub struct SiloDiff {
+    pub from: String,
+    pub to: String,
+    pub config: Vec<Change<Value>>,
+    pub winnowing: Vec<Change<String>>,
+    pub rules: Vec<ModuleRulesDiff>,
+    pub modules: Vec<Change<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+impl SiloDiff {
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty()
+            && self.winnowing.is_empty()
+            && self.rules.is_empty()
+            && self.modules.is_empty()
+    }
+}
+
+fn flatten(prefix: &str, value: Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+
+                flatten(&key, value, out);
+            }
+        }
+        other => {
+            out.insert(prefix.to_string(), other);
+        }
+    }
+}
+
+fn diff_maps<T: PartialEq + Clone>(
+    before: &BTreeMap<String, T>,
+    after: &BTreeMap<String, T>,
+) -> Vec<Change<T>> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| Change {
+            key: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect()
+}
+
+fn effective_config(silo: &Silo, notes: &mut Vec<String>) -> Config {
+    let Some(raw) = &silo.raw_config else {
+        return silo.config_snapshot.clone();
+    };
+
+    match toml::from_str(raw) {
+        Ok(config) => config,
+        Err(e) => {
+            notes.push(format!(
+                "{}: raw config does not parse, using struct snapshot ({})",
+                silo.id,
+                e.message()
+            ));
+
+            silo.config_snapshot.clone()
+        }
+    }
+}
+
+fn config_keys(config: &Config) -> Result<BTreeMap<String, Value>> {
+    let mut value = serde_json::to_value(config)?;
+
+    if let Value::Object(map) = &mut value {
+        map.remove("winnowing");
+    }
+
+    let mut keys = BTreeMap::new();
+
+    flatten("", value, &mut keys);
+
+    Ok(keys)
+}
+
+fn rule_keys(content: &str) -> BTreeMap<String, Value> {
+    let mut keys = BTreeMap::new();
+
+    match serde_json::from_str::<inventory::ModuleRules>(content)
+        .ok()
+        .and_then(|rules| serde_json::to_value(rules).ok())
+    {
+        Some(value) => flatten("", value, &mut keys),
+        None => {
+            keys.insert("raw".to_string(), Value::String(content.to_string()));
+        }
+    }
+
+    keys
+}
+
+fn module_rules(rules: &BTreeMap<String, String>) -> BTreeMap<String, BTreeMap<String, Value>> {
+    rules
+        .iter()
+        .map(|(name, content)| {
+            let module = name.strip_suffix(".json").unwrap_or(name).to_string();
+
+            (module, rule_keys(content))
+        })
+        .collect()
+}
+
+fn module_state(flags: &ModuleFlags) -> String {
+    let set: Vec<&str> = [
+        (flags.disable, "disabled"),
+        (flags.remove, "remove"),
+        (flags.skip_mount, "skip_mount"),
+    ]
+    .into_iter()
+    .filter_map(|(on, name)| on.then_some(name))
+    .collect();
+
+    if set.is_empty() {
+        "enabled".to_string()
+    } else {
+        set.join("+")
+    }
+}
+
+fn module_states(modules: &[ModuleFlags]) -> BTreeMap<String, String> {
+    modules
+        .iter()
+        .map(|flags| (flags.id.clone(), module_state(flags)))
+        .collect()
+}
+
+pub fn diff(a: &Silo, b: &Silo) -> Result<SiloDiff> {
+    let mut notes = Vec::new();
+
+    let config_a = effective_config(a, &mut notes);
+
+    let config_b = effective_config(b, &mut notes);
+
+    let config = diff_maps(&config_keys(&config_a)?, &config_keys(&config_b)?);
+
+    let winnowing = diff_maps(
+        &config_a.winnowing.rules.clone().into_iter().collect(),
+        &config_b.winnowing.rules.clone().into_iter().collect(),
+    );
+
+    let rules = match (&a.rules, &b.rules) {
+        (Some(rules_a), Some(rules_b)) => {
+            let modules_a = module_rules(rules_a);
+
+            let modules_b = module_rules(rules_b);
+
+            let empty = BTreeMap::new();
+
+            let ids: BTreeSet<&String> = modules_a.keys().chain(modules_b.keys()).collect();
+
+            ids.into_iter()
+                .map(|id| ModuleRulesDiff {
+                    module: id.clone(),
+                    changes: diff_maps(
+                        modules_a.get(id).unwrap_or(&empty),
+                        modules_b.get(id).unwrap_or(&empty),
+                    ),
+                })
+                .filter(|diff| !diff.changes.is_empty())
+                .collect()
+        }
+        _ => {
+            notes.push("module rules are not recorded in both silos".to_string());
+
+            Vec::new()
+        }
+    };
+
+    let modules = match (&a.modules, &b.modules) {
+        (Some(modules_a), Some(modules_b)) => {
+            diff_maps(&module_states(modules_a), &module_states(modules_b))
+        }
+        _ => {
+            notes.push("module enable states are not recorded in both silos".to_string());
+
+            Vec::new()
+        }
+    };
+
+    Ok(SiloDiff {
+        from: a.id.clone(),
+        to: b.id.clone(),
+        config,
+        winnowing,
+        rules,
+        modules,
+        notes,
+    })
+}
+
+pub fn diff_silos(a: &str, b: &str) -> Result<SiloDiff> {
+    diff(&granary::load_silo(a)?, &granary::load_silo(b)?)
+}
@@ -0,0 +1,469 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, hash_map::Entry},
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt, symlink},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::fs::{
+    AtFlags, CWD, FileType, Gid, Mode, Timespec, Timestamps, Uid, chownat, mknodat, utimensat,
+};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{core::integrity::EntryKind, defs, erofs, utils};
+
+const TREE_VERSION: u32 = 1;
+
+const INCOMING_NAME: &str = ".incoming";
+
+const FLAG_FILES: [&str; 3] = [
+    defs::DISABLE_FILE_NAME,
+    defs::REMOVE_FILE_NAME,
+    defs::SKIP_MOUNT_FILE_NAME,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+
+pub struct TreeEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub mtime: i64,
+    #[serde(default)]
+    pub mtime_nsec: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rdev: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+
+pub struct Tree {
+    pub version: u32,
+    pub entries: Vec<TreeEntry>,
+}
+
+pub struct ObjectStore {
+    root: PathBuf,
+}
+
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn is_object_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn is_safe_relative(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+impl ObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn path(&self, id: &str) -> Result<PathBuf> {
+        if !is_object_id(id) {
+            bail!("Invalid object id: {}", id);
+        }
+
+        Ok(self.root.join(&id[..2]).join(&id[2..]))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.path(id).map(|p| p.is_file()).unwrap_or(false)
+    }
+
+    fn incoming(&self) -> Result<PathBuf> {
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("Failed to create {}", self.root.display()))?;
+
+        let staging = self.root.join(INCOMING_NAME);
+
+        let _ = fs::remove_file(&staging);
+
+        Ok(staging)
+    }
+
+    fn ingest(&self, staging: &Path) -> Result<String> {
+        let id = hex(&utils::sha256_file(staging)?);
+
+        let dst = self.path(&id)?;
+
+        if dst.is_file() {
+            fs::remove_file(staging)?;
+
+            return Ok(id);
+        }
+
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::set_permissions(staging, fs::Permissions::from_mode(0o600))?;
+
+        fs::rename(staging, &dst).with_context(|| format!("Failed to store object {}", id))?;
+
+        Ok(id)
+    }
+
+    pub fn put_file(&self, src: &Path) -> Result<String> {
+        let staging = self.incoming()?;
+
+        utils::reflink_or_copy(src, &staging)
+            .with_context(|| format!("Failed to copy {}", src.display()))?;
+
+        self.ingest(&staging)
+    }
+
+    pub fn put_bytes(&self, data: &[u8]) -> Result<String> {
+        let staging = self.incoming()?;
+
+        fs::write(&staging, data)?;
+
+        self.ingest(&staging)
+    }
+
+    pub fn read(&self, id: &str) -> Result<Vec<u8>> {
+        let path = self.path(id)?;
+
+        fs::read(&path).with_context(|| format!("Missing object {}", id))
+    }
+
+    pub fn load_tree(&self, id: &str) -> Result<Tree> {
+        let tree: Tree = serde_json::from_slice(&self.read(id)?)
+            .with_context(|| format!("Failed to parse tree {}", id))?;
+
+        if tree.version != TREE_VERSION {
+            bail!("Unsupported tree version {} in {}", tree.version, id);
+        }
+
+        Ok(tree)
+    }
+
+    pub fn tree_objects(&self, id: &str) -> Result<Vec<String>> {
+        let tree = self.load_tree(id)?;
+
+        let mut objects = vec![id.to_string()];
+
+        objects.extend(tree.entries.into_iter().filter_map(|e| e.object));
+
+        Ok(objects)
+    }
+
+    pub fn gc(&self, live: &HashSet<String>) -> Result<(usize, u64)> {
+        let mut removed = 0;
+
+        let mut freed = 0;
+
+        if !self.root.exists() {
+            return Ok((removed, freed));
+        }
+
+        for entry in WalkDir::new(&self.root)
+            .min_depth(2)
+            .max_depth(2)
+            .into_iter()
+            .flatten()
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let Ok(relative) = entry.path().strip_prefix(&self.root) else {
+                continue;
+            };
+
+            let id: String = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+
+            if live.contains(&id) {
+                continue;
+            }
+
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+
+            match fs::remove_file(entry.path()) {
+                Ok(_) => {
+                    removed += 1;
+
+                    freed += size;
+                }
+                Err(e) => log::warn!("Failed to remove object {}: {}", id, e),
+            }
+        }
+
+        Ok((removed, freed))
+    }
+}
+
+fn describe(
+    store: &ObjectStore,
+    relative: String,
+    path: &Path,
+    cache: &HashMap<&str, &TreeEntry>,
+) -> Result<TreeEntry> {
+    let meta = fs::symlink_metadata(path)?;
+
+    let file_type = meta.file_type();
+
+    let kind = if file_type.is_dir() {
+        EntryKind::Dir
+    } else if file_type.is_file() {
+        EntryKind::File
+    } else if file_type.is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::Other
+    };
+
+    let mut entry = TreeEntry {
+        path: relative,
+        kind,
+        mode: meta.mode(),
+        uid: meta.uid(),
+        gid: meta.gid(),
+        size: 0,
+        mtime: meta.mtime(),
+        mtime_nsec: meta.mtime_nsec(),
+        object: None,
+        target: None,
+        rdev: None,
+        link: None,
+        xattrs: erofs::read_xattrs(path),
+    };
+
+    match kind {
+        EntryKind::File => {
+            entry.size = meta.len();
+
+            let cached = cache.get(entry.path.as_str()).and_then(|previous| {
+                let unchanged = previous.kind == EntryKind::File
+                    && previous.size == entry.size
+                    && previous.mtime == entry.mtime
+                    && previous.mtime_nsec == entry.mtime_nsec;
+
+                previous
+                    .object
+                    .clone()
+                    .filter(|id| unchanged && store.contains(id))
+            });
+
+            entry.object = Some(match cached {
+                Some(id) => id,
+                None => store.put_file(path)?,
+            });
+        }
+        EntryKind::Symlink => {
+            entry.target = Some(fs::read_link(path)?.to_string_lossy().to_string());
+        }
+        EntryKind::Other => entry.rdev = Some(meta.rdev()),
+        EntryKind::Dir => {}
+    }
+
+    Ok(entry)
+}
+
+pub fn snapshot_module(
+    store: &ObjectStore,
+    module_dir: &Path,
+    previous: Option<&Tree>,
+) -> Result<String> {
+    let cache: HashMap<&str, &TreeEntry> = previous
+        .map(|tree| tree.entries.iter().map(|e| (e.path.as_str(), e)).collect())
+        .unwrap_or_default();
+
+    let mut entries = Vec::new();
+
+    let mut links: HashMap<(u64, u64), String> = HashMap::new();
+
+    for entry in WalkDir::new(module_dir)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || !FLAG_FILES.iter().any(|f| e.file_name() == *f))
+    {
+        let entry = entry?;
+
+        let relative = entry
+            .path()
+            .strip_prefix(module_dir)?
+            .to_string_lossy()
+            .to_string();
+
+        let mut described = describe(store, relative, entry.path(), &cache)
+            .with_context(|| format!("Failed to snapshot {}", entry.path().display()))?;
+
+        let meta = entry.metadata()?;
+
+        if !meta.is_dir() && meta.nlink() > 1 {
+            match links.entry((meta.dev(), meta.ino())) {
+                Entry::Occupied(first) => described.link = Some(first.get().clone()),
+                Entry::Vacant(slot) => {
+                    slot.insert(described.path.clone());
+                }
+            }
+        }
+
+        entries.push(described);
+    }
+
+    let tree = Tree {
+        version: TREE_VERSION,
+        entries,
+    };
+
+    store.put_bytes(&serde_json::to_vec(&tree)?)
+}
+
+fn apply_metadata(entry: &TreeEntry, path: &Path, lost: &mut Vec<String>) {
+    if let Err(e) = chownat(
+        CWD,
+        path,
+        Some(Uid::from_raw(entry.uid)),
+        Some(Gid::from_raw(entry.gid)),
+        AtFlags::SYMLINK_NOFOLLOW,
+    ) {
+        lost.push(format!("{}: ownership ({})", path.display(), e));
+    }
+
+    if entry.kind != EntryKind::Symlink
+        && let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(entry.mode & 0o7777))
+    {
+        lost.push(format!("{}: mode ({})", path.display(), e));
+    }
+
+    lost.extend(utils::set_xattrs(path, entry.xattrs.clone()));
+
+    if entry.mtime == 0 && entry.mtime_nsec == 0 {
+        return;
+    }
+
+    let mtime = Timespec {
+        tv_sec: entry.mtime,
+        tv_nsec: entry.mtime_nsec as _,
+    };
+
+    let times = Timestamps {
+        last_access: mtime,
+        last_modification: mtime,
+    };
+
+    if let Err(e) = utimensat(CWD, path, &times, AtFlags::SYMLINK_NOFOLLOW) {
+        lost.push(format!("{}: mtime ({})", path.display(), e));
+    }
+}
+
+fn create_entry(store: &ObjectStore, entry: &TreeEntry, target: &Path) -> Result<()> {
+    let path = target.join(&entry.path);
+
+    if let Some(first) = &entry.link {
+        if !is_safe_relative(first) {
+            bail!("Unsafe link in entry {}: {}", entry.path, first);
+        }
+
+        fs::hard_link(target.join(first), &path)?;
+
+        return Ok(());
+    }
+
+    match entry.kind {
+        EntryKind::Dir => fs::create_dir_all(&path)?,
+        EntryKind::File => {
+            let Some(id) = &entry.object else {
+                bail!("File entry {} has no object", entry.path);
+            };
+
+            utils::reflink_or_copy(&store.path(id)?, &path)
+                .with_context(|| format!("Failed to restore object {}", id))?;
+        }
+        EntryKind::Symlink => {
+            let Some(link) = &entry.target else {
+                bail!("Symlink entry {} has no target", entry.path);
+            };
+
+            symlink(link, &path)?;
+        }
+        EntryKind::Other => mknodat(
+            CWD,
+            &path,
+            FileType::from_raw_mode(entry.mode),
+            Mode::from_raw_mode(entry.mode),
+            entry.rdev.unwrap_or(0),
+        )?,
+    }
+
+    Ok(())
+}
+
+pub fn restore_module(store: &ObjectStore, tree_id: &str, target: &Path) -> Result<()> {
+    let tree = store.load_tree(tree_id)?;
+
+    if target.exists() {
+        fs::remove_dir_all(target)?;
+    }
+
+    fs::create_dir_all(target)?;
+
+    for entry in &tree.entries {
+        if entry.path.is_empty() {
+            continue;
+        }
+
+        if !is_safe_relative(&entry.path) {
+            bail!("Unsafe path in tree {}: {}", tree_id, entry.path);
+        }
+
+        create_entry(store, entry, target)
+            .with_context(|| format!("Failed to restore {}", entry.path))?;
+    }
+
+    let mut lost = Vec::new();
+
+    for entry in tree.entries.iter().rev() {
+        let path = if entry.path.is_empty() {
+            target.to_path_buf()
+        } else {
+            target.join(&entry.path)
+        };
+
+        apply_metadata(entry, &path, &mut lost);
+    }
+
+    if !lost.is_empty() {
+        log::warn!(
+            "{} attributes could not be restored into {}",
+            lost.len(),
+            target.display()
+        );
+
+        for item in &lost {
+            log::debug!("  {}", item);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,477 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::Config,
+    core::{inventory, state::RuntimeState, sync},
+    defs,
+    mount::mountinfo::MountTable,
+    utils,
+};
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+
+pub enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+
+pub struct ManifestEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub mtime: i64,
+    #[serde(default)]
+    pub mtime_nsec: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub source_size: u64,
+    #[serde(default)]
+    pub source_mtime: i64,
+    #[serde(default)]
+    pub source_mtime_nsec: i64,
+    #[serde(default)]
+    pub source_mode: u32,
+    #[serde(default)]
+    pub source_uid: u32,
+    #[serde(default)]
+    pub source_gid: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+
+pub struct Manifest {
+    pub version: u32,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+pub enum Depth {
+    Quick,
+    Full,
+}
+
+#[derive(Debug, Default, Serialize)]
+
+pub struct VerifyReport {
+    pub module: String,
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub mismatched: Vec<String>,
+    pub unexpected: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub repaired: bool,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+            && self.missing.is_empty()
+            && self.mismatched.is_empty()
+            && self.unexpected.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+
+        format!(
+            "{} checked, {} missing, {} mismatched, {} unexpected",
+            self.checked,
+            self.missing.len(),
+            self.mismatched.len(),
+            self.unexpected.len()
+        )
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn relative_paths(module_root: &Path) -> Vec<(String, PathBuf)> {
+    WalkDir::new(module_root)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+        .filter(|e| e.depth() != 1 || e.file_name() != defs::INTEGRITY_FILE_NAME)
+        .filter_map(|e| {
+            let relative = e.path().strip_prefix(module_root).ok()?;
+
+            Some((relative.to_string_lossy().to_string(), e.into_path()))
+        })
+        .collect()
+}
+
+fn describe(relative: String, path: &Path, hash: bool) -> Result<ManifestEntry> {
+    let meta = fs::symlink_metadata(path)?;
+
+    let file_type = meta.file_type();
+
+    let kind = if file_type.is_dir() {
+        EntryKind::Dir
+    } else if file_type.is_file() {
+        EntryKind::File
+    } else if file_type.is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::Other
+    };
+
+    let sha256 = if hash && kind == EntryKind::File {
+        Some(hex(&utils::sha256_file(path)?))
+    } else {
+        None
+    };
+
+    let target = if kind == EntryKind::Symlink {
+        Some(fs::read_link(path)?.to_string_lossy().to_string())
+    } else {
+        None
+    };
+
+    Ok(ManifestEntry {
+        path: relative,
+        kind,
+        size: if kind == EntryKind::File {
+            meta.len()
+        } else {
+            0
+        },
+        mtime: meta.mtime(),
+        mtime_nsec: meta.mtime_nsec(),
+        sha256,
+        target,
+        source_size: 0,
+        source_mtime: 0,
+        source_mtime_nsec: 0,
+        source_mode: 0,
+        source_uid: 0,
+        source_gid: 0,
+    })
+}
+
+impl Manifest {
+    pub fn build(module_root: &Path, source_root: &Path) -> Result<Self> {
+        let entries = relative_paths(module_root)
+            .into_par_iter()
+            .map(|(relative, path)| {
+                let mut entry = describe(relative, &path, true)?;
+
+                if let Ok(source) = fs::symlink_metadata(source_root.join(&entry.path)) {
+                    entry.source_size = source.len();
+
+                    entry.source_mtime = source.mtime();
+
+                    entry.source_mtime_nsec = source.mtime_nsec();
+
+                    entry.source_mode = source.mode();
+
+                    entry.source_uid = source.uid();
+
+                    entry.source_gid = source.gid();
+                }
+
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            entries,
+        })
+    }
+
+    pub fn load(module_root: &Path) -> Result<Self> {
+        let path = module_root.join(defs::INTEGRITY_FILE_NAME);
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, module_root: &Path) -> Result<()> {
+        let path = module_root.join(defs::INTEGRITY_FILE_NAME);
+
+        let staging = path.with_extension("json.tmp");
+
+        fs::write(&staging, serde_json::to_string(self)?)?;
+
+        fs::rename(&staging, &path)?;
+
+        Ok(())
+    }
+}
+
+fn entry_matches(expected: &ManifestEntry, path: &Path, depth: Depth) -> bool {
+    let Ok(actual) = describe(expected.path.clone(), path, false) else {
+        return false;
+    };
+
+    if actual.kind != expected.kind || actual.target != expected.target {
+        return false;
+    }
+
+    if expected.kind != EntryKind::File {
+        return true;
+    }
+
+    if actual.size != expected.size {
+        return false;
+    }
+
+    let unchanged = actual.mtime == expected.mtime && actual.mtime_nsec == expected.mtime_nsec;
+
+    if depth == Depth::Quick && unchanged {
+        return true;
+    }
+
+    match (&expected.sha256, utils::sha256_file(path)) {
+        (Some(expected), Ok(digest)) => *expected == hex(&digest),
+        _ => false,
+    }
+}
+
+pub fn verify(module_root: &Path, depth: Depth) -> VerifyReport {
+    let mut report = VerifyReport {
+        module: module_root
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let manifest = match Manifest::load(module_root) {
+        Ok(manifest) if manifest.version == MANIFEST_VERSION => manifest,
+        Ok(manifest) => {
+            report.error = Some(format!("unsupported manifest version {}", manifest.version));
+
+            return report;
+        }
+        Err(e) => {
+            report.error = Some(format!("{:#}", e));
+
+            return report;
+        }
+    };
+
+    let results: Vec<(String, bool, bool)> = manifest
+        .entries
+        .par_iter()
+        .map(|entry| {
+            let path = module_root.join(&entry.path);
+
+            let exists = fs::symlink_metadata(&path).is_ok();
+
+            let matches = exists && entry_matches(entry, &path, depth);
+
+            (entry.path.clone(), exists, matches)
+        })
+        .collect();
+
+    report.checked = results.len();
+
+    for (path, exists, matches) in results {
+        if !exists {
+            report.missing.push(path);
+        } else if !matches {
+            report.mismatched.push(path);
+        }
+    }
+
+    let known: HashSet<&str> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+
+    report.unexpected = relative_paths(module_root)
+        .into_iter()
+        .map(|(relative, _)| relative)
+        .filter(|relative| !known.contains(relative.as_str()))
+        .collect();
+
+    report
+}
+
+pub fn write_manifest(module_root: &Path, module: &inventory::Module) {
+    match Manifest::build(module_root, &module.source_path).and_then(|m| m.save(module_root)) {
+        Ok(_) => log::debug!("Integrity manifest written for {}", module.id),
+        Err(e) => log::warn!(
+            "Failed to write integrity manifest for {}: {:#}",
+            module.id,
+            e
+        ),
+    }
+}
+
+fn storage_users(mount_point: &Path) -> Vec<PathBuf> {
+    let mut users: Vec<PathBuf> = [MountTable::read_self(), MountTable::read_pid(1)]
+        .into_iter()
+        .flatten()
+        .flat_map(|table| {
+            table
+                .users_of(mount_point)
+                .into_iter()
+                .map(|e| e.mount_point.clone())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    users.sort();
+
+    users.dedup();
+
+    users
+}
+
+fn writable_storage() -> Result<PathBuf> {
+    let state = RuntimeState::load().unwrap_or_default();
+
+    let mount_point = if state.mount_point.as_os_str().is_empty() {
+        PathBuf::from(defs::HYBRID_MNT_DIR)
+    } else {
+        state.mount_point.clone()
+    };
+
+    if !utils::is_mounted(&mount_point) {
+        bail!("storage is not mounted at {}", mount_point.display());
+    }
+
+    if matches!(state.storage_mode.as_str(), "erofs" | "direct") {
+        bail!("{} storage is not a synced copy", state.storage_mode);
+    }
+
+    Ok(mount_point)
+}
+
+fn ensure_idle(mount_point: &Path, force: bool) -> Result<()> {
+    let users = storage_users(mount_point);
+
+    if !users.is_empty() {
+        let listed = users
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if !force {
+            bail!(
+                "storage at {} is in use by {}; rewriting the layers of live mounts is undefined, reboot to apply module changes or pass --force",
+                mount_point.display(),
+                listed
+            );
+        }
+
+        log::warn!(
+            "Rewriting storage at {} while it is in use by {}; mounted files may misbehave until the next boot",
+            mount_point.display(),
+            listed
+        );
+    }
+
+    Ok(())
+}
+
+pub fn verify_storage(config: &Config, repair: bool, force: bool) -> Result<Vec<VerifyReport>> {
+    let mount_point = writable_storage()?;
+
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    let candidates: Vec<&inventory::Module> = modules
+        .iter()
+        .filter(|m| sync::is_storage_candidate(m))
+        .collect();
+
+    let mut reports: Vec<VerifyReport> = candidates
+        .par_iter()
+        .map(|module| {
+            let mut report = verify(&mount_point.join(&module.id), Depth::Full);
+
+            report.module = module.id.clone();
+
+            report
+        })
+        .collect();
+
+    if repair {
+        let failing: Vec<&inventory::Module> = candidates
+            .iter()
+            .zip(&reports)
+            .filter(|(_, report)| !report.is_ok())
+            .map(|(module, _)| *module)
+            .collect();
+
+        if !failing.is_empty() {
+            ensure_idle(&mount_point, force)?;
+
+            log::info!(
+                "Re-syncing {} module(s) that failed verification",
+                failing.len()
+            );
+
+            let repaired: HashSet<&str> = sync::resync_modules(&failing, &modules, &mount_point)
+                .iter()
+                .map(|m| m.id.as_str())
+                .collect();
+
+            for report in &mut reports {
+                report.repaired = repaired.contains(report.module.as_str());
+            }
+        }
+    }
+
+    Ok(reports)
+}
+
+pub fn resync_storage(config: &Config, ids: &[String], force: bool) -> Result<Vec<String>> {
+    let mount_point = writable_storage()?;
+
+    ensure_idle(&mount_point, force)?;
+
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    if let Some(unknown) = ids.iter().find(|id| !modules.iter().any(|m| &m.id == *id)) {
+        bail!("module {} is not enabled", unknown);
+    }
+
+    let selected: Vec<&inventory::Module> = modules
+        .iter()
+        .filter(|m| sync::is_storage_candidate(m))
+        .filter(|m| ids.is_empty() || ids.contains(&m.id))
+        .collect();
+
+    log::info!("Forcing full re-sync of {} module(s)", selected.len());
+
+    Ok(sync::resync_modules(&selected, &modules, &mount_point)
+        .iter()
+        .map(|m| m.id.clone())
+        .collect())
+}
@@ -1,8 +1,14 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod attrs;
+pub mod capabilities;
+pub mod dedup;
 pub mod executor;
 pub mod granary;
+pub mod granary_diff;
+pub mod granary_store;
+pub mod integrity;
 pub mod inventory;
 pub mod modules;
 pub mod planner;
@@ -15,7 +21,10 @@ use std::path::Path;
 
 use anyhow::Result;
 
-use crate::{conf::config::Config, try_umount};
+use crate::{
+    conf::config::{Config, StorageKind},
+    try_umount, utils,
+};
 
 pub struct Init;
 
@@ -60,17 +69,77 @@ impl OryzaEngine<Init> {
         mnt_base: &Path,
         img_path: &Path,
     ) -> Result<OryzaEngine<StorageReady>> {
-        let handle = storage::setup(
-            mnt_base,
-            img_path,
-            &self.config.moduledir,
-            self.config.force_ext4,
-            self.config.use_erofs,
-            &self.config.mountsource,
-            self.config.disable_umount,
-        )?;
+        let preference = self.config.storage_preference();
+
+        let tmpfs_limit = if preference.contains(&StorageKind::Tmpfs) {
+            self.config.storage.tmpfs_budget.and_then(|budget| {
+                let limit = budget.resolve(utils::mem_total());
 
-        log::info!(">> Storage Backend: [{}]", handle.mode.to_uppercase());
+                if limit.is_none() {
+                    log::warn!("MemTotal unavailable, ignoring tmpfs budget {}", budget);
+                }
+
+                limit
+            })
+        } else {
+            None
+        };
+
+        let modules = if preference.contains(&StorageKind::Erofs) || tmpfs_limit.is_some() {
+            inventory::scan(&self.config.moduledir, &self.config)
+                .map_err(|e| log::warn!("Pre-storage module scan failed: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let fingerprint = if preference.contains(&StorageKind::Erofs) {
+            modules
+                .as_deref()
+                .map(|m| sync::content_fingerprint(m, &self.config.storage.erofs))
+        } else {
+            None
+        };
+
+        let payload = if tmpfs_limit.is_some() {
+            modules.as_deref().map(sync::estimate_payload)
+        } else {
+            None
+        };
+
+        if let (Some(limit), Some(payload)) = (tmpfs_limit, payload) {
+            log::info!(
+                "Estimated module payload: {} bytes (tmpfs budget {} bytes)",
+                payload,
+                limit
+            );
+        }
+
+        let ctx = storage::StorageContext {
+            mnt_base: mnt_base.to_path_buf(),
+            img_path: img_path.to_path_buf(),
+            moduledir: self.config.moduledir.clone(),
+            mount_source: self.config.mountsource.clone(),
+            disable_umount: self.config.disable_umount,
+            shrink_ext4: self.config.storage.shrink_ext4,
+            partitions: self.config.partitions.clone(),
+            fingerprint,
+            payload,
+            tmpfs_limit,
+            erofs: self.config.storage.erofs.clone(),
+            sync_hash: self.config.storage.sync_hash,
+        };
+
+        let handle = storage::setup(ctx, &preference)?;
+
+        log::info!(
+            ">> Storage Backend: [{}]",
+            handle.kind().as_str().to_uppercase()
+        );
+
+        if let Some(device) = handle.loop_device() {
+            log::info!(">> Backing Loop Device: {}", device.display());
+        }
 
         Ok(OryzaEngine {
             config: self.config,
@@ -88,9 +157,9 @@ impl OryzaEngine<StorageReady> {
             modules.len()
         );
 
-        sync::perform_sync(&modules, &self.state.handle.mount_point)?;
+        self.state.handle.populate(&modules)?;
 
-        self.state.handle.commit(self.config.disable_umount)?;
+        self.state.handle.commit()?;
 
         Ok(OryzaEngine {
             config: self.config,
@@ -104,12 +173,14 @@ impl OryzaEngine<StorageReady> {
 
 impl OryzaEngine<ModulesReady> {
     pub fn generate_plan(self) -> Result<OryzaEngine<Planned>> {
-        let plan = planner::generate(
+        let mut plan = planner::generate(
             &self.config,
             &self.state.modules,
-            &self.state.handle.mount_point,
+            self.state.handle.mount_point(),
         )?;
 
+        plan.source_layers = self.state.handle.binds_source();
+
         plan.print_visuals();
 
         Ok(OryzaEngine {
@@ -145,11 +216,11 @@ impl OryzaEngine<Executed> {
     pub fn finalize(self) -> Result<()> {
         let mut nuke_active = false;
 
-        if self.state.handle.mode == "ext4" && self.config.enable_nuke {
+        if self.state.handle.kind() == StorageKind::Ext4 && self.config.enable_nuke {
             log::info!(">> Engaging Paw Pad Protocol (Stealth)...");
 
             match try_umount::ksu_nuke_sysfs(
-                self.state.handle.mount_point.to_string_lossy().as_ref(),
+                self.state.handle.mount_point().to_string_lossy().as_ref(),
             ) {
                 Ok(_) => {
                     log::info!(">> Success: Paw Pad active. Sysfs traces purged.");
@@ -163,13 +234,13 @@ impl OryzaEngine<Executed> {
         }
 
         modules::update_description(
-            &self.state.handle.mode,
+            self.state.handle.kind(),
             nuke_active,
             self.state.result.overlay_module_ids.len(),
             self.state.result.magic_module_ids.len(),
         );
 
-        let storage_stats = storage::get_usage(&self.state.handle.mount_point);
+        let storage_stats = self.state.handle.usage();
 
         let active_mounts: Vec<String> = self
             .state
@@ -179,9 +250,11 @@ impl OryzaEngine<Executed> {
             .map(|op| op.partition_name.clone())
             .collect();
 
-        let state = state::RuntimeState::new(
-            self.state.handle.mode,
-            self.state.handle.mount_point,
+        let propagation = self.state.result.propagation;
+
+        let mut state = state::RuntimeState::new(
+            self.state.handle.kind().as_str().to_string(),
+            self.state.handle.mount_point().to_path_buf(),
             self.state.result.overlay_module_ids,
             self.state.result.magic_module_ids,
             nuke_active,
@@ -189,6 +262,14 @@ impl OryzaEngine<Executed> {
             storage_stats,
         );
 
+        state.record_resizes(self.state.handle.resizes());
+
+        state.module_usage = storage::module_usage(self.state.handle.mount_point());
+
+        state.storage_dedup = self.state.handle.dedup();
+
+        state.propagation = propagation;
+
         if let Err(e) = state.save() {
             log::error!("Failed to save runtime state: {:#}", e);
         }
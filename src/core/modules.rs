@@ -13,7 +13,7 @@ use anyhow::Result;
 use serde::Serialize;
 
 use crate::{
-    conf::config::Config,
+    conf::config::{Config, StorageKind},
     core::{
         inventory::{self, MountMode},
         state::RuntimeState,
@@ -54,6 +54,10 @@ impl From<&Path> for ModuleProp {
     }
 }
 
+pub fn read_version(module_dir: &Path) -> String {
+    ModuleProp::from(module_dir.join("module.prop").as_path()).version
+}
+
 #[derive(Serialize)]
 
 struct ModuleInfo {
@@ -151,7 +155,7 @@ pub fn print_list(config: &Config) -> Result<()> {
 }
 
 pub fn update_description(
-    storage_mode: &str,
+    storage_kind: StorageKind,
     nuke_active: bool,
     overlay_count: usize,
     magic_count: usize,
@@ -162,16 +166,18 @@ pub fn update_description(
         return;
     }
 
-    let mode_str = match storage_mode {
-        "tmpfs" => "Tmpfs",
-        "erofs" => "EROFS",
-        _ => "Ext4",
+    let mode_str = match storage_kind {
+        StorageKind::Tmpfs => "Tmpfs",
+        StorageKind::Erofs => "EROFS",
+        StorageKind::Ext4 => "Ext4",
+        StorageKind::Direct => "Direct",
     };
 
-    let status_emoji = match storage_mode {
-        "tmpfs" => "🐾",
-        "erofs" => "🚀",
-        _ => "💿",
+    let status_emoji = match storage_kind {
+        StorageKind::Tmpfs => "🐾",
+        StorageKind::Erofs => "🚀",
+        StorageKind::Ext4 => "💿",
+        StorageKind::Direct => "🔗",
     };
 
     let nuke_str = if nuke_active {
@@ -14,16 +14,23 @@ use walkdir::WalkDir;
 
 use crate::{
     conf::config,
-    core::inventory::{Module, MountMode},
+    core::{
+        capabilities::{self, Support},
+        inventory::{Module, MountMode},
+    },
     defs,
+    mount::overlay::OverlayMethod,
 };
 
+const STOCK_ROOT_ESTIMATE: usize = "/proc/self/fd/".len() + 8;
+
 #[derive(Debug, Clone)]
 
 pub struct OverlayOperation {
     pub partition_name: String,
     pub target: String,
     pub lowerdirs: Vec<PathBuf>,
+    pub method: OverlayMethod,
 }
 
 #[derive(Debug, Default)]
@@ -33,6 +40,7 @@ pub struct MountPlan {
     pub magic_module_paths: Vec<PathBuf>,
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
+    pub source_layers: bool,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -118,7 +126,13 @@ impl MountPlan {
 
                 let branch = if is_last_op { "╰──" } else { "├──" };
 
-                log::info!("{} [Target: {}] {}", branch, op.partition_name, op.target);
+                log::info!(
+                    "{} [Target: {}] {} (method: {})",
+                    branch,
+                    op.partition_name,
+                    op.target,
+                    op.method.as_str()
+                );
 
                 let prefix = if is_last_op { "    " } else { "│   " };
 
@@ -174,6 +188,20 @@ pub fn generate(
 ) -> Result<MountPlan> {
     let mut plan = MountPlan::default();
 
+    let overlay_support = capabilities::get().overlay.supported;
+
+    let overlay_supported = overlay_support != Support::No;
+
+    match overlay_support {
+        Support::No => {
+            log::warn!("OverlayFS is not supported by this kernel, routing modules to Magic Mount.")
+        }
+        Support::Unknown => {
+            log::info!("OverlayFS support could not be confirmed, keeping overlay routing.")
+        }
+        Support::Yes => {}
+    }
+
     let mut target_partitions = defs::BUILTIN_PARTITIONS.to_vec();
 
     target_partitions.extend(config.partitions.iter().map(|s| s.as_str()));
@@ -220,6 +248,11 @@ pub fn generate(
                     let mode = module.rules.get_mode(&dir_name);
 
                     match mode {
+                        MountMode::Overlay if !overlay_supported => {
+                            contrib.magic_path = Some(content_path.clone());
+
+                            has_any_action = true;
+                        }
                         MountMode::Overlay => {
                             contrib.overlays.push((dir_name, path));
 
@@ -293,10 +326,17 @@ pub fn generate(
             continue;
         }
 
+        let lowerdir_len = layers
+            .iter()
+            .map(|l| l.as_os_str().len() + 1)
+            .sum::<usize>()
+            + STOCK_ROOT_ESTIMATE;
+
         plan.overlay_ops.push(OverlayOperation {
             partition_name: part,
             target: resolved_target.to_string_lossy().to_string(),
             lowerdirs: layers,
+            method: OverlayMethod::select(lowerdir_len),
         });
     }
 
@@ -12,6 +12,47 @@ use serde::{Deserialize, Serialize};
 
 use crate::defs;
 
+const MAX_RESIZE_HISTORY: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+
+pub struct StorageResize {
+    pub timestamp: u64,
+    pub from_size: u64,
+    pub to_size: u64,
+    pub payload: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+
+pub struct ModuleUsage {
+    pub id: String,
+    pub apparent_size: u64,
+    pub disk_size: u64,
+    pub files: u64,
+    pub partitions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+
+pub struct DedupStats {
+    pub files: u64,
+    pub bytes_saved: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+
+pub struct PropagationRecord {
+    pub partition: String,
+    pub target: PathBuf,
+    pub mode: String,
+    pub applied: bool,
+    #[serde(default)]
+    pub bound: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 
 pub struct RuntimeState {
@@ -32,6 +73,30 @@ pub struct RuntimeState {
     pub storage_percent: u8,
     #[serde(default)]
     pub zygisksu_enforce: bool,
+    #[serde(default)]
+    pub storage_resizes: Vec<StorageResize>,
+    #[serde(default)]
+    pub module_usage: Vec<ModuleUsage>,
+    #[serde(default)]
+    pub storage_dedup: DedupStats,
+    #[serde(default)]
+    pub propagation: Vec<PropagationRecord>,
+}
+
+impl StorageResize {
+    pub fn new(from_size: u64, to_size: u64, payload: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Self {
+            timestamp,
+            from_size,
+            to_size,
+            payload,
+        }
+    }
 }
 
 impl RuntimeState {
@@ -70,9 +135,27 @@ impl RuntimeState {
             storage_used: storage_info.1,
             storage_percent: storage_info.2,
             zygisksu_enforce,
+            storage_resizes: Vec::new(),
+            module_usage: Vec::new(),
+            storage_dedup: DedupStats::default(),
+            propagation: Vec::new(),
         }
     }
 
+    pub fn record_resizes(&mut self, resizes: &[StorageResize]) {
+        let mut history = Self::load()
+            .map(|previous| previous.storage_resizes)
+            .unwrap_or_default();
+
+        history.extend_from_slice(resizes);
+
+        let excess = history.len().saturating_sub(MAX_RESIZE_HISTORY);
+
+        history.drain(..excess);
+
+        self.storage_resizes = history;
+    }
+
     pub fn save(&self) -> Result<()> {
         let json = serde_json::to_string_pretty(self)?;
 
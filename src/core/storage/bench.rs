@@ -0,0 +1,275 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use super::erofs::native_compression;
+use crate::{
+    conf::config::{Config, ErofsCompressor, ErofsOptions},
+    core::{inventory, sync},
+    defs,
+    erofs::Compression,
+    utils,
+};
+
+const COMPRESSORS: [ErofsCompressor; 5] = [
+    ErofsCompressor::None,
+    ErofsCompressor::Lz4,
+    ErofsCompressor::Lz4hc,
+    ErofsCompressor::Lzma,
+    ErofsCompressor::Deflate,
+];
+
+enum Builder {
+    Native(Compression, Option<u64>),
+    Mkfs(ErofsOptions),
+}
+
+struct Variant {
+    name: String,
+    builder: Builder,
+}
+
+#[derive(Serialize)]
+
+struct BenchResult {
+    name: String,
+    builder: &'static str,
+    build_ms: u64,
+    image_size: u64,
+    read_bytes: u64,
+    read_ms: u64,
+    read_mib_per_sec: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BenchResult {
+    fn new(variant: &Variant) -> Self {
+        Self {
+            name: variant.name.clone(),
+            builder: match variant.builder {
+                Builder::Native(..) => "native",
+                Builder::Mkfs(_) => "mkfs",
+            },
+            build_ms: 0,
+            image_size: 0,
+            read_bytes: 0,
+            read_ms: 0,
+            read_mib_per_sec: 0.0,
+            error: None,
+        }
+    }
+}
+
+fn variants(configured: &ErofsOptions) -> Vec<Variant> {
+    let mut variants = vec![
+        Variant {
+            name: "native-none".to_string(),
+            builder: Builder::Native(Compression::None, None),
+        },
+        Variant {
+            name: "native-lz4".to_string(),
+            builder: Builder::Native(Compression::Lz4, None),
+        },
+    ];
+
+    for compressor in COMPRESSORS {
+        variants.push(Variant {
+            name: format!("mkfs-{}", compressor.as_str()),
+            builder: Builder::Mkfs(ErofsOptions {
+                compressor: Some(compressor),
+                ..Default::default()
+            }),
+        });
+    }
+
+    if configured.needs_mkfs() {
+        variants.push(Variant {
+            name: "configured".to_string(),
+            builder: Builder::Mkfs(configured.clone()),
+        });
+    } else if configured.timestamp.is_some() {
+        variants.push(Variant {
+            name: "configured".to_string(),
+            builder: Builder::Native(native_compression(configured), configured.timestamp),
+        });
+    }
+
+    variants
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+fn drop_caches() {
+    if let Err(e) = fs::write("/proc/sys/vm/drop_caches", "3") {
+        log::debug!("Failed to drop caches: {}", e);
+    }
+}
+
+fn read_tree(root: &Path) -> Result<u64> {
+    let mut total = 0;
+
+    let mut buf = vec![0u8; 128 * 1024];
+
+    for entry in WalkDir::new(root).follow_links(false).into_iter().flatten() {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let mut file = File::open(entry.path())
+            .with_context(|| format!("Failed to open {}", entry.path().display()))?;
+
+        loop {
+            let n = file.read(&mut buf)?;
+
+            if n == 0 {
+                break;
+            }
+
+            total += n as u64;
+        }
+    }
+
+    Ok(total)
+}
+
+fn measure_read(image: &Path, mount_point: &Path, result: &mut BenchResult) -> Result<()> {
+    drop_caches();
+
+    let device = utils::mount_erofs_image(image, mount_point)?;
+
+    let start = Instant::now();
+
+    let read = read_tree(mount_point);
+
+    let elapsed = start.elapsed();
+
+    let _ = unmount(mount_point, UnmountFlags::DETACH);
+
+    if let Err(e) = device.detach() {
+        log::debug!("Failed to detach {}: {:#}", device.path.display(), e);
+    }
+
+    result.read_bytes = read?;
+
+    result.read_ms = millis(elapsed);
+
+    if elapsed.as_secs_f64() > 0.0 {
+        result.read_mib_per_sec =
+            result.read_bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64();
+    }
+
+    Ok(())
+}
+
+fn run_variant(
+    variant: &Variant,
+    modules: &[inventory::Module],
+    stage: &Path,
+    work_dir: &Path,
+) -> BenchResult {
+    let mut result = BenchResult::new(variant);
+
+    let image = work_dir.join(format!("{}.erofs", variant.name));
+
+    let mount_point = work_dir.join("mnt");
+
+    let start = Instant::now();
+
+    let built = match &variant.builder {
+        Builder::Native(compression, timestamp) => {
+            sync::pack_erofs(modules, &image, *compression, *timestamp).map(|_| ())
+        }
+        Builder::Mkfs(options) => utils::create_erofs_image(stage, &image, options),
+    };
+
+    result.build_ms = millis(start.elapsed());
+
+    let outcome = built.and_then(|_| {
+        result.image_size = fs::metadata(&image)?.len();
+
+        measure_read(&image, &mount_point, &mut result)
+    });
+
+    if let Err(e) = outcome {
+        log::warn!("Benchmark {} failed: {:#}", variant.name, e);
+
+        result.error = Some(format!("{:#}", e));
+    }
+
+    let _ = fs::remove_file(&image);
+
+    result
+}
+
+fn print_text(results: &[BenchResult]) {
+    println!(
+        "{:<16} {:>10} {:>14} {:>10} {:>12}",
+        "variant", "build ms", "image bytes", "read ms", "read MiB/s"
+    );
+
+    for result in results {
+        match &result.error {
+            Some(error) => println!("{:<16} failed: {}", result.name, error),
+            None => println!(
+                "{:<16} {:>10} {:>14} {:>10} {:>12.1}",
+                result.name,
+                result.build_ms,
+                result.image_size,
+                result.read_ms,
+                result.read_mib_per_sec
+            ),
+        }
+    }
+}
+
+pub fn run(config: &Config, text: bool) -> Result<()> {
+    let modules = inventory::scan(&config.moduledir, config)
+        .context("Failed to scan modules for storage benchmark")?;
+
+    let work_dir = PathBuf::from(defs::RUN_DIR).join("bench");
+
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+
+    let stage = work_dir.join("stage");
+
+    utils::ensure_dir_exists(&stage)?;
+
+    let result = sync::perform_sync(&modules, &stage, false).map(|_| {
+        variants(&config.storage.erofs)
+            .iter()
+            .map(|variant| run_variant(variant, &modules, &stage, &work_dir))
+            .collect::<Vec<_>>()
+    });
+
+    if let Err(e) = fs::remove_dir_all(&work_dir) {
+        log::warn!("Failed to clean up {}: {}", work_dir.display(), e);
+    }
+
+    let results = result.context("Failed to stage modules for storage benchmark")?;
+
+    if text {
+        print_text(&results);
+
+        return Ok(());
+    }
+
+    println!("{}", serde_json::to_string(&results)?);
+
+    Ok(())
+}
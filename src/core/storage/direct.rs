@@ -0,0 +1,136 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::{
+    fd::AsFd,
+    fs::CWD,
+    mount::{
+        MoveMountFlags, OpenTreeFlags, UnmountFlags, mount_bind, move_mount, open_tree, unmount,
+    },
+};
+
+use super::{StorageBackend, StorageContext, remove_stale_file};
+use crate::{
+    conf::config::StorageKind,
+    core::{capabilities, inventory::Module, state::DedupStats, sync},
+    defs, utils,
+};
+
+pub struct DirectBackend {
+    ctx: StorageContext,
+}
+
+impl DirectBackend {
+    pub fn new(ctx: StorageContext) -> Self {
+        Self { ctx }
+    }
+
+    fn partitions(&self) -> impl Iterator<Item = &str> {
+        defs::BUILTIN_PARTITIONS
+            .iter()
+            .copied()
+            .chain(self.ctx.partitions.iter().map(String::as_str))
+    }
+}
+
+impl StorageBackend for DirectBackend {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Direct
+    }
+
+    fn context(&self) -> &StorageContext {
+        &self.ctx
+    }
+
+    fn setup(&mut self) -> Result<()> {
+        if !utils::is_overlay_xattr_supported(&self.ctx.moduledir) {
+            bail!("{} rejected overlay xattrs", self.ctx.moduledir.display());
+        }
+
+        utils::mount_tmpfs(&self.ctx.mnt_base, &self.ctx.mount_source, None)?;
+
+        remove_stale_file(&self.ctx.img_path);
+
+        remove_stale_file(&self.ctx.erofs_path());
+
+        remove_stale_file(&self.ctx.erofs_fingerprint_path());
+
+        Ok(())
+    }
+
+    fn populate(&mut self, modules: &[Module]) -> Result<DedupStats> {
+        let mut bound: Vec<PathBuf> = Vec::new();
+
+        for module in modules.iter().filter(|m| sync::is_storage_candidate(m)) {
+            for partition in self.partitions() {
+                let source = module.source_path.join(partition);
+
+                if !source.is_dir() {
+                    continue;
+                }
+
+                let target = self.ctx.mnt_base.join(&module.id).join(partition);
+
+                let result = fs::create_dir_all(&target)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|_| bind_tree(&source, &target))
+                    .with_context(|| format!("Failed to bind {} for {}", partition, module.id));
+
+                if let Err(e) = result {
+                    for target in bound.iter().rev() {
+                        if let Err(e) = unmount(target, UnmountFlags::DETACH) {
+                            log::warn!("Failed to unbind {}: {}", target.display(), e);
+                        }
+                    }
+
+                    return Err(e);
+                }
+
+                bound.push(target);
+            }
+        }
+
+        log::info!(
+            ">> Bound {} partition trees in place (attribute manifests and context repair are not applied).",
+            bound.len()
+        );
+
+        Ok(DedupStats::default())
+    }
+
+    fn binds_source(&self) -> bool {
+        true
+    }
+}
+
+fn bind_tree(source: &Path, target: &Path) -> Result<()> {
+    let caps = capabilities::get();
+
+    if !(caps.new_mount_api && caps.open_tree) {
+        return mount_bind(source, target).context("bind mount failed");
+    }
+
+    let tree = open_tree(
+        CWD,
+        source,
+        OpenTreeFlags::OPEN_TREE_CLONE
+            | OpenTreeFlags::OPEN_TREE_CLOEXEC
+            | OpenTreeFlags::AT_RECURSIVE,
+    )
+    .with_context(|| format!("clone tree of {}", source.display()))?;
+
+    move_mount(
+        tree.as_fd(),
+        "",
+        CWD,
+        target,
+        MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+    )
+    .with_context(|| format!("attach clone to {}", target.display()))
+}
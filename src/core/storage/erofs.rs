@@ -0,0 +1,222 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{UnmountFlags, unmount};
+
+use super::{StorageBackend, StorageContext, remove_stale_file};
+use crate::{
+    conf::config::{ErofsCompressor, ErofsOptions, StorageKind},
+    core::{
+        capabilities,
+        inventory::Module,
+        state::{DedupStats, RuntimeState},
+        sync,
+    },
+    erofs::Compression,
+    mount::loopdev::LoopDevice,
+    utils,
+};
+
+pub struct ErofsBackend {
+    ctx: StorageContext,
+    staged: bool,
+    cached: bool,
+    loop_device: Option<LoopDevice>,
+}
+
+impl ErofsBackend {
+    pub fn new(ctx: StorageContext) -> Self {
+        Self {
+            ctx,
+            staged: false,
+            cached: false,
+            loop_device: None,
+        }
+    }
+
+    fn try_mount_cached(&mut self) -> bool {
+        let Some(fingerprint) = &self.ctx.fingerprint else {
+            return false;
+        };
+
+        let image_path = self.ctx.erofs_path();
+
+        let stored = fs::read_to_string(self.ctx.erofs_fingerprint_path()).unwrap_or_default();
+
+        if !image_path.exists() || stored.trim() != fingerprint {
+            log::debug!("EROFS cache miss, image will be rebuilt");
+
+            return false;
+        }
+
+        match utils::mount_erofs_image(&image_path, &self.ctx.mnt_base) {
+            Ok(device) => {
+                log::info!("EROFS cache hit, mounted {}", image_path.display());
+
+                self.loop_device = Some(device);
+
+                true
+            }
+            Err(e) => {
+                log::warn!("Cached EROFS image unusable: {:#}", e);
+
+                false
+            }
+        }
+    }
+
+    fn pack_native(&mut self, modules: &[Module]) -> Result<DedupStats> {
+        let options = &self.ctx.erofs;
+
+        let image_path = self.ctx.erofs_path();
+
+        let stats = sync::pack_erofs(
+            modules,
+            &image_path,
+            native_compression(options),
+            options.timestamp,
+        )?;
+
+        let device = utils::mount_erofs_image(&image_path, &self.ctx.mnt_base)
+            .context("Failed to mount EROFS image")?;
+
+        self.loop_device = Some(device);
+
+        Ok(DedupStats {
+            files: stats.shared_files as u64,
+            bytes_saved: stats.shared_bytes,
+        })
+    }
+
+    fn store_fingerprint(&self) {
+        if let Some(fingerprint) = &self.ctx.fingerprint
+            && let Err(e) = fs::write(self.ctx.erofs_fingerprint_path(), fingerprint)
+        {
+            log::warn!("Failed to store EROFS fingerprint: {}", e);
+        }
+    }
+}
+
+impl StorageBackend for ErofsBackend {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Erofs
+    }
+
+    fn context(&self) -> &StorageContext {
+        &self.ctx
+    }
+
+    fn setup(&mut self) -> Result<()> {
+        let caps = capabilities::get();
+
+        if !caps.erofs.supported {
+            bail!("kernel does not support erofs");
+        }
+
+        if let Some(algorithm) = self.ctx.erofs.compressor.and_then(|c| c.kernel_algorithm())
+            && !caps.erofs.compressors.is_empty()
+            && !caps.erofs.compressors.iter().any(|c| c == algorithm)
+        {
+            bail!("kernel lacks {} decompression for erofs", algorithm);
+        }
+
+        remove_stale_file(&self.ctx.img_path);
+
+        if self.try_mount_cached() {
+            self.cached = true;
+
+            return Ok(());
+        }
+
+        remove_stale_file(&self.ctx.erofs_fingerprint_path());
+
+        Ok(())
+    }
+
+    fn populate(&mut self, modules: &[Module]) -> Result<DedupStats> {
+        if self.cached {
+            log::info!(">> Module content unchanged, reusing cached storage image.");
+
+            return Ok(RuntimeState::load()
+                .map(|s| s.storage_dedup)
+                .unwrap_or_default());
+        }
+
+        if self.ctx.erofs.needs_mkfs() {
+            log::info!("Configured EROFS options require mkfs.erofs, staging modules");
+        } else {
+            match self.pack_native(modules) {
+                Ok(dedup) => {
+                    self.hide();
+
+                    self.store_fingerprint();
+
+                    return Ok(dedup);
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Native EROFS packing failed, staging for mkfs.erofs: {:#}",
+                        e
+                    );
+
+                    remove_stale_file(&self.ctx.erofs_path());
+                }
+            }
+        }
+
+        utils::mount_tmpfs(&self.ctx.mnt_base, &self.ctx.mount_source, None)?;
+
+        self.staged = true;
+
+        self.hide();
+
+        sync::perform_sync(modules, &self.ctx.mnt_base, self.ctx.sync_hash)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if !self.staged {
+            return Ok(());
+        }
+
+        let image_path = self.ctx.erofs_path();
+
+        let mount_point = &self.ctx.mnt_base;
+
+        utils::create_erofs_image(mount_point, &image_path, &self.ctx.erofs)
+            .context("Failed to pack EROFS image")?;
+
+        unmount(mount_point, UnmountFlags::DETACH).context("Failed to unmount staging tmpfs")?;
+
+        let device = utils::mount_erofs_image(&image_path, mount_point)
+            .context("Failed to mount finalized EROFS image")?;
+
+        self.loop_device = Some(device);
+
+        self.hide();
+
+        self.staged = false;
+
+        self.store_fingerprint();
+
+        Ok(())
+    }
+}
+
+pub(super) fn native_compression(options: &ErofsOptions) -> Compression {
+    match options.compressor {
+        Some(ErofsCompressor::None) => Compression::None,
+        Some(_) => Compression::Lz4,
+        None if capabilities::get()
+            .erofs
+            .compressors
+            .iter()
+            .any(|c| c == "lz4") =>
+        {
+            Compression::Lz4
+        }
+        None => Compression::None,
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{UnmountFlags, unmount};
+use walkdir::WalkDir;
+
+use super::{StorageBackend, StorageContext, get_usage};
+use crate::{
+    conf::config::StorageKind,
+    core::{
+        inventory::Module,
+        state::{DedupStats, StorageResize},
+        sync,
+    },
+    mount::loopdev::LoopDevice,
+    utils,
+};
+
+const OVERHEAD: u64 = 64 * 1024 * 1024;
+
+const GRANULARITY: u64 = 5 * 1024 * 1024;
+
+const MIN_FREE: u64 = 16 * 1024 * 1024;
+
+pub struct Ext4Backend {
+    ctx: StorageContext,
+    resizes: Vec<StorageResize>,
+    loop_device: Option<LoopDevice>,
+}
+
+impl Ext4Backend {
+    pub fn new(ctx: StorageContext) -> Self {
+        Self {
+            ctx,
+            resizes: Vec::new(),
+            loop_device: None,
+        }
+    }
+
+    fn plan_resize(&self, payload: u64) -> Result<Option<u64>> {
+        let image_size = fs::metadata(&self.ctx.img_path)?.len();
+
+        let (total, used, _) = get_usage(&self.ctx.mnt_base);
+
+        let target = target_size(payload);
+
+        if payload + MIN_FREE > total {
+            log::info!(
+                "modules.img too small: payload {} bytes, capacity {} bytes",
+                payload,
+                total
+            );
+
+            return Ok(Some(target.max(image_size + GRANULARITY)));
+        }
+
+        if self.ctx.shrink_ext4 && target * 2 < image_size && used * 2 < total {
+            log::info!(
+                "modules.img mostly unused: payload {} bytes, image {} bytes",
+                payload,
+                image_size
+            );
+
+            return Ok(Some(target));
+        }
+
+        Ok(None)
+    }
+
+    fn resize(&mut self, new_size: u64, payload: u64) -> Result<()> {
+        let img_path = &self.ctx.img_path;
+
+        let target = &self.ctx.mnt_base;
+
+        let from_size = fs::metadata(img_path)?.len();
+
+        if let Err(e) = unmount(target, UnmountFlags::empty()) {
+            log::warn!("modules.img is busy, keeping {} bytes: {}", from_size, e);
+
+            return Ok(());
+        }
+
+        self.loop_device = None;
+
+        let result = if new_size > from_size {
+            grow_image(img_path, new_size).inspect_err(|_| {
+                if let Err(e) = truncate_image(img_path, from_size) {
+                    log::warn!("Failed to restore modules.img size: {:#}", e);
+                }
+            })
+        } else {
+            shrink_image(img_path, new_size)
+        };
+
+        match utils::mount_image(img_path, target) {
+            Ok(device) => self.loop_device = Some(device),
+            Err(e) => {
+                return Err(e).context(match result {
+                    Ok(_) => "Failed to remount modules.img".to_string(),
+                    Err(resize) => format!("Failed to remount modules.img after {:#}", resize),
+                });
+            }
+        }
+
+        self.hide();
+
+        result.context("Resize aborted, modules.img was remounted")?;
+
+        log::info!(
+            "Resized modules.img: {} -> {} bytes (payload {} bytes)",
+            from_size,
+            new_size,
+            payload
+        );
+
+        self.resizes
+            .push(StorageResize::new(from_size, new_size, payload));
+
+        Ok(())
+    }
+}
+
+impl StorageBackend for Ext4Backend {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Ext4
+    }
+
+    fn context(&self) -> &StorageContext {
+        &self.ctx
+    }
+
+    fn setup(&mut self) -> Result<()> {
+        let img_path = &self.ctx.img_path;
+
+        let target = &self.ctx.mnt_base;
+
+        if !img_path.exists() {
+            if let Some(parent) = img_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            create_image(img_path, &self.ctx.moduledir).context("Failed to create modules.img")?;
+        }
+
+        let device = match utils::mount_image(img_path, target) {
+            Ok(device) => device,
+            Err(e) => {
+                log::warn!("Failed to mount modules.img: {:#}", e);
+
+                if utils::repair_image(img_path).is_err() {
+                    bail!("Failed to repair modules.img");
+                }
+
+                utils::mount_image(img_path, target)
+                    .context("Failed to mount modules.img after repair")?
+            }
+        };
+
+        self.loop_device = Some(device);
+
+        Ok(())
+    }
+
+    fn populate(&mut self, modules: &[Module]) -> Result<DedupStats> {
+        let payload = sync::estimate_payload(modules);
+
+        match self.plan_resize(payload) {
+            Ok(Some(new_size)) => self
+                .resize(new_size, payload)
+                .context("Failed to resize modules.img")?,
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to check modules.img capacity: {:#}", e),
+        }
+
+        sync::perform_sync(modules, &self.ctx.mnt_base, self.ctx.sync_hash)
+    }
+
+    fn resizes(&self) -> &[StorageResize] {
+        &self.resizes
+    }
+
+    fn loop_device(&self) -> Option<&LoopDevice> {
+        self.loop_device.as_ref()
+    }
+}
+
+fn target_size(payload: u64) -> u64 {
+    (payload + OVERHEAD).div_ceil(GRANULARITY) * GRANULARITY
+}
+
+fn grow_image(path: &Path, new_size: u64) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(new_size)
+        .context("Failed to extend modules.img")?;
+
+    utils::repair_image(path)?;
+
+    utils::resize_image(path, None)
+}
+
+fn shrink_image(path: &Path, new_size: u64) -> Result<()> {
+    utils::repair_image(path)?;
+
+    utils::resize_image(path, Some(new_size))?;
+
+    truncate_image(path, new_size)
+}
+
+fn truncate_image(path: &Path, size: u64) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(size)
+        .context("Failed to truncate modules.img")
+}
+
+fn create_image(path: &Path, moduledir: &Path) -> Result<()> {
+    let mut total_size: u64 = 0;
+
+    if moduledir.exists() {
+        for entry in WalkDir::new(moduledir).into_iter().flatten() {
+            if entry.metadata().map(|m| m.is_file()).unwrap_or(false) {
+                total_size += entry.metadata().unwrap().len();
+            }
+        }
+    }
+
+    let size_str = format!("{}", target_size(total_size));
+
+    let status = Command::new("truncate")
+        .arg("-s")
+        .arg(&size_str)
+        .arg(path)
+        .status()?;
+
+    if !status.success() {
+        bail!("Failed to allocate image file");
+    }
+
+    let status = Command::new("mkfs.ext4")
+        .arg("-O")
+        .arg("^has_journal")
+        .arg(path)
+        .status()?;
+
+    if !status.success() {
+        bail!("Failed to format image file");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,442 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+pub mod bench;
+mod direct;
+mod erofs;
+mod ext4;
+mod tmpfs;
+
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use rustix::{
+    fs::Mode,
+    mount::{UnmountFlags, unmount},
+};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::{ErofsOptions, StorageKind},
+    core::{
+        inventory::Module,
+        state::{DedupStats, ModuleUsage, RuntimeState, StorageResize},
+        sync,
+    },
+    defs,
+    mount::{loopdev::LoopDevice, mountinfo::MountTable},
+    selinux, utils,
+};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::try_umount::send_unmountable;
+
+#[derive(Debug, Clone)]
+
+pub struct StorageContext {
+    pub mnt_base: PathBuf,
+    pub img_path: PathBuf,
+    pub moduledir: PathBuf,
+    pub mount_source: String,
+    pub disable_umount: bool,
+    pub shrink_ext4: bool,
+    pub partitions: Vec<String>,
+    pub fingerprint: Option<String>,
+    pub payload: Option<u64>,
+    pub tmpfs_limit: Option<u64>,
+    pub erofs: ErofsOptions,
+    pub sync_hash: bool,
+}
+
+impl StorageContext {
+    pub fn erofs_path(&self) -> PathBuf {
+        self.img_path.with_extension("erofs")
+    }
+
+    pub fn erofs_fingerprint_path(&self) -> PathBuf {
+        self.img_path.with_extension("erofs.sha256")
+    }
+}
+
+pub trait StorageBackend: Send {
+    fn kind(&self) -> StorageKind;
+
+    fn context(&self) -> &StorageContext;
+
+    fn setup(&mut self) -> Result<()>;
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn populate(&mut self, modules: &[Module]) -> Result<DedupStats> {
+        sync::perform_sync(modules, self.mount_point(), self.context().sync_hash)
+    }
+
+    fn mount_point(&self) -> &Path {
+        &self.context().mnt_base
+    }
+
+    fn resizes(&self) -> &[StorageResize] {
+        &[]
+    }
+
+    fn binds_source(&self) -> bool {
+        false
+    }
+
+    fn loop_device(&self) -> Option<&LoopDevice> {
+        None
+    }
+
+    fn usage(&self) -> (u64, u64, u8) {
+        get_usage(self.mount_point())
+    }
+
+    fn teardown(&mut self) -> Result<()> {
+        if utils::is_mounted(self.mount_point()) {
+            unmount(self.mount_point(), UnmountFlags::DETACH)?;
+        }
+
+        if let Some(device) = self.loop_device() {
+            device.detach()?;
+        }
+
+        Ok(())
+    }
+
+    fn hide(&self) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if !self.context().disable_umount {
+            let _ = send_unmountable(self.mount_point());
+        }
+    }
+}
+
+pub struct StorageHandle {
+    backend: Box<dyn StorageBackend>,
+    fallbacks: Vec<StorageKind>,
+    dedup: DedupStats,
+}
+
+impl StorageHandle {
+    pub fn kind(&self) -> StorageKind {
+        self.backend.kind()
+    }
+
+    pub fn mount_point(&self) -> &Path {
+        self.backend.mount_point()
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        self.backend.commit()
+    }
+
+    pub fn populate(&mut self, modules: &[Module]) -> Result<()> {
+        loop {
+            let error = match self.backend.populate(modules) {
+                Ok(dedup) => {
+                    self.dedup = dedup;
+
+                    return Ok(());
+                }
+                Err(e) if self.fallbacks.is_empty() => return Err(e),
+                Err(e) => e,
+            };
+
+            let kind = self.backend.kind();
+
+            log::warn!(
+                "Storage backend {} failed to populate: {:#}",
+                kind.as_str(),
+                error
+            );
+
+            if let Err(e) = self.backend.teardown() {
+                log::debug!("Teardown of {} failed: {:#}", kind.as_str(), e);
+            }
+
+            let ctx = self.backend.context().clone();
+
+            let (backend, fallbacks) = first_available(&ctx, &self.fallbacks)?;
+
+            log::info!(
+                ">> Storage Backend fell back to [{}]",
+                backend.kind().as_str().to_uppercase()
+            );
+
+            self.backend = backend;
+
+            self.fallbacks = fallbacks;
+        }
+    }
+
+    pub fn binds_source(&self) -> bool {
+        self.backend.binds_source()
+    }
+
+    pub fn dedup(&self) -> DedupStats {
+        self.dedup
+    }
+
+    pub fn usage(&self) -> (u64, u64, u8) {
+        self.backend.usage()
+    }
+
+    pub fn resizes(&self) -> &[StorageResize] {
+        self.backend.resizes()
+    }
+
+    pub fn loop_device(&self) -> Option<&Path> {
+        self.backend.loop_device().map(|d| d.path.as_path())
+    }
+}
+
+#[derive(Serialize)]
+
+struct StorageStatus {
+    #[serde(rename = "type")]
+    mode: String,
+    mount_point: String,
+    usage_percent: u8,
+    total_size: u64,
+    used_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<DedupStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modules: Option<Vec<ModuleUsage>>,
+}
+
+pub fn get_usage(path: &Path) -> (u64, u64, u8) {
+    if let Ok(stat) = rustix::fs::statvfs(path) {
+        let total = stat.f_blocks * stat.f_frsize;
+
+        let free = stat.f_bfree * stat.f_frsize;
+
+        let used = total - free;
+
+        let percent = if total > 0 {
+            (used * 100 / total) as u8
+        } else {
+            0
+        };
+
+        (total, used, percent)
+    } else {
+        (0, 0, 0)
+    }
+}
+
+pub fn module_usage(mount_point: &Path) -> Vec<ModuleUsage> {
+    let Ok(entries) = fs::read_dir(mount_point) else {
+        return Vec::new();
+    };
+
+    let mut seen = HashSet::new();
+
+    let mut usage: Vec<ModuleUsage> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir() && e.file_name() != "lost+found")
+        .map(|entry| {
+            let root = entry.path();
+
+            let mut module = ModuleUsage {
+                id: entry.file_name().to_string_lossy().to_string(),
+                ..Default::default()
+            };
+
+            module.partitions = defs::BUILTIN_PARTITIONS
+                .iter()
+                .filter(|p| root.join(p).is_dir())
+                .map(|p| p.to_string())
+                .collect();
+
+            for item in WalkDir::new(&root)
+                .follow_links(false)
+                .into_iter()
+                .flatten()
+            {
+                let Ok(meta) = item.metadata() else {
+                    continue;
+                };
+
+                if meta.nlink() > 1 && !meta.is_dir() && !seen.insert((meta.dev(), meta.ino())) {
+                    continue;
+                }
+
+                if !meta.is_dir() {
+                    module.files += 1;
+
+                    module.apparent_size += meta.len();
+                }
+
+                module.disk_size += meta.blocks() * 512;
+            }
+
+            module
+        })
+        .collect();
+
+    usage.sort_by(|a, b| b.disk_size.cmp(&a.disk_size).then(a.id.cmp(&b.id)));
+
+    usage
+}
+
+fn create_backend(kind: StorageKind, ctx: &StorageContext) -> Box<dyn StorageBackend> {
+    match kind {
+        StorageKind::Tmpfs => Box::new(tmpfs::TmpfsBackend::new(ctx.clone())),
+        StorageKind::Ext4 => Box::new(ext4::Ext4Backend::new(ctx.clone())),
+        StorageKind::Erofs => Box::new(erofs::ErofsBackend::new(ctx.clone())),
+        StorageKind::Direct => Box::new(direct::DirectBackend::new(ctx.clone())),
+    }
+}
+
+fn remove_stale_file(path: &Path) {
+    if path.exists()
+        && let Err(e) = fs::remove_file(path)
+    {
+        log::warn!("Failed to remove unused {}: {}", path.display(), e);
+    }
+}
+
+pub fn setup(ctx: StorageContext, preference: &[StorageKind]) -> Result<StorageHandle> {
+    if let Ok(table) = MountTable::read_self() {
+        let stale: Vec<_> = table.mounts_at(&ctx.mnt_base).collect();
+
+        if !stale.is_empty() {
+            log::info!(
+                "Detaching {} stale mount(s) at {} ({})",
+                stale.len(),
+                ctx.mnt_base.display(),
+                stale
+                    .iter()
+                    .map(|m| m.fs_type.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        for _ in stale {
+            let _ = unmount(&ctx.mnt_base, UnmountFlags::DETACH);
+        }
+    }
+
+    let (backend, fallbacks) = first_available(&ctx, preference)?;
+
+    Ok(StorageHandle {
+        backend,
+        fallbacks,
+        dedup: DedupStats::default(),
+    })
+}
+
+fn first_available(
+    ctx: &StorageContext,
+    preference: &[StorageKind],
+) -> Result<(Box<dyn StorageBackend>, Vec<StorageKind>)> {
+    let mut failures = Vec::new();
+
+    for (index, kind) in preference.iter().enumerate() {
+        let mut backend = create_backend(*kind, ctx);
+
+        match backend.setup() {
+            Ok(_) => {
+                backend.hide();
+
+                return Ok((backend, preference[index + 1..].to_vec()));
+            }
+            Err(e) => {
+                log::warn!("Storage backend {} unavailable: {:#}", kind.as_str(), e);
+
+                if let Err(e) = backend.teardown() {
+                    log::debug!("Teardown of {} failed: {:#}", kind.as_str(), e);
+                }
+
+                failures.push(format!("{}: {:#}", kind.as_str(), e));
+            }
+        }
+    }
+
+    bail!(
+        "No storage backend could be set up [{}]",
+        failures.join("; ")
+    )
+}
+
+#[allow(dead_code)]
+
+pub fn finalize_storage_permissions(target: &Path) {
+    if let Err(e) = rustix::fs::chmod(target, Mode::from(0o755)) {
+        log::warn!("Failed to chmod storage root: {}", e);
+    }
+
+    if let Err(e) = rustix::fs::chown(
+        target,
+        Some(rustix::fs::Uid::from_raw(0)),
+        Some(rustix::fs::Gid::from_raw(0)),
+    ) {
+        log::warn!("Failed to chown storage root: {}", e);
+    }
+
+    if let Err(e) = selinux::setfilecon_strict(target, selinux::DEFAULT_CONTEXT) {
+        log::warn!("Failed to set SELinux context: {}", e);
+    }
+}
+
+pub fn print_status(per_module: bool) -> Result<()> {
+    let state = RuntimeState::load().ok();
+
+    let (mnt_base, expected_mode) = if let Some(ref s) = state {
+        (s.mount_point.clone(), s.storage_mode.clone())
+    } else {
+        (PathBuf::from(defs::HYBRID_MNT_DIR), "unknown".to_string())
+    };
+
+    let mut mode = "unknown".to_string();
+
+    let mut total = 0;
+
+    let mut used = 0;
+
+    let mut percent = 0;
+
+    if utils::is_mounted(&mnt_base)
+        && let Ok(stat) = rustix::fs::statvfs(&mnt_base)
+    {
+        mode = if expected_mode != "unknown" {
+            expected_mode
+        } else {
+            "active".to_string()
+        };
+
+        total = stat.f_blocks * stat.f_frsize;
+
+        let free = stat.f_bfree * stat.f_frsize;
+
+        used = total - free;
+
+        if total > 0 {
+            percent = (used * 100 / total) as u8;
+        }
+    }
+
+    let status = StorageStatus {
+        mode,
+        mount_point: mnt_base.to_string_lossy().to_string(),
+        usage_percent: percent,
+        total_size: total,
+        used_size: used,
+        dedup: state.as_ref().map(|s| s.storage_dedup),
+        modules: per_module.then(|| module_usage(&mnt_base)),
+    };
+
+    println!("{}", serde_json::to_string(&status)?);
+
+    Ok(())
+}
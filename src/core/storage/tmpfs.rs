@@ -0,0 +1,63 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Result, bail};
+
+use super::{StorageBackend, StorageContext, remove_stale_file};
+use crate::{conf::config::StorageKind, core::capabilities, utils};
+
+pub struct TmpfsBackend {
+    ctx: StorageContext,
+}
+
+impl TmpfsBackend {
+    pub fn new(ctx: StorageContext) -> Self {
+        Self { ctx }
+    }
+}
+
+impl StorageBackend for TmpfsBackend {
+    fn kind(&self) -> StorageKind {
+        StorageKind::Tmpfs
+    }
+
+    fn context(&self) -> &StorageContext {
+        &self.ctx
+    }
+
+    fn setup(&mut self) -> Result<()> {
+        let caps = capabilities::get();
+
+        if caps.new_mount_api && !caps.tmpfs_xattr {
+            bail!("tmpfs lacks trusted xattr support");
+        }
+
+        if let (Some(limit), Some(payload)) = (self.ctx.tmpfs_limit, self.ctx.payload)
+            && payload > limit
+        {
+            bail!(
+                "module payload of {} bytes exceeds tmpfs budget of {} bytes",
+                payload,
+                limit
+            );
+        }
+
+        utils::mount_tmpfs(
+            &self.ctx.mnt_base,
+            &self.ctx.mount_source,
+            self.ctx.tmpfs_limit,
+        )?;
+
+        if !caps.tmpfs_xattr && !utils::is_overlay_xattr_supported(&self.ctx.mnt_base) {
+            bail!("tmpfs rejected overlay xattrs");
+        }
+
+        remove_stale_file(&self.ctx.img_path);
+
+        remove_stale_file(&self.ctx.erofs_path());
+
+        remove_stale_file(&self.ctx.erofs_fingerprint_path());
+
+        Ok(())
+    }
+}
@@ -1,56 +1,666 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{collections::HashSet, fs, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
 
 use crate::{
-    core::inventory::{Module, MountMode},
-    defs, utils,
+    conf::config::ErofsOptions,
+    core::{
+        attrs::AttrManifest,
+        dedup, integrity,
+        inventory::{Module, MountMode},
+        state::DedupStats,
+    },
+    defs,
+    erofs::{BuildStats, Compression, EntryMeta, ErofsWriter, read_xattrs},
+    selinux, utils,
 };
 
-pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
+pub fn perform_sync(
+    modules: &[Module],
+    target_base: &Path,
+    verify_content: bool,
+) -> Result<DedupStats> {
     log::info!("Starting smart module sync to {}", target_base.display());
 
     prune_orphaned_modules(modules, target_base)?;
 
-    modules.par_iter().for_each(|module| {
-        if matches!(module.rules.default_mode, MountMode::Magic) {
-            log::debug!("Skipping sync for Magic Mount module: {}", module.id);
+    let synced: Vec<&Module> = modules
+        .par_iter()
+        .filter(|module| {
+            if matches!(module.rules.default_mode, MountMode::Magic) {
+                log::debug!("Skipping sync for Magic Mount module: {}", module.id);
+
+                return false;
+            }
+
+            if !has_content(module) {
+                log::debug!("Skipping module: {}", module.id);
+
+                return false;
+            }
+
+            update_module(module, &target_base.join(&module.id), verify_content)
+        })
+        .collect();
+
+    let outcome = dedup::dedup_tree(target_base);
+
+    let stale: Vec<&Module> = modules
+        .iter()
+        .filter(|m| outcome.relinked.contains(&m.id) || synced.iter().any(|s| s.id == m.id))
+        .collect();
+
+    write_manifests(&stale, target_base);
+
+    Ok(outcome.stats)
+}
+
+pub fn resync_modules<'a>(
+    selected: &[&'a Module],
+    modules: &[Module],
+    target_base: &Path,
+) -> Vec<&'a Module> {
+    let synced: Vec<&Module> = selected
+        .par_iter()
+        .copied()
+        .filter(|module| sync_module(module, &target_base.join(&module.id)))
+        .collect();
 
-            return;
+    let outcome = dedup::dedup_tree(target_base);
+
+    let relinked: Vec<&Module> = modules
+        .iter()
+        .filter(|m| outcome.relinked.contains(&m.id) || synced.iter().any(|s| s.id == m.id))
+        .collect();
+
+    write_manifests(&relinked, target_base);
+
+    synced
+}
+
+fn update_module(module: &Module, dst: &Path, verify_content: bool) -> bool {
+    if !dst.exists() {
+        log::info!("Syncing module: {} (New)", module.id);
+
+        return sync_module(module, dst);
+    }
+
+    let manifest = match integrity::Manifest::load(dst) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            log::info!("Syncing module: {} (Untracked: {:#})", module.id, e);
+
+            return sync_module(module, dst);
         }
+    };
 
-        let dst = target_base.join(&module.id);
+    if !is_intact(dst, &module.id) {
+        log::info!("Syncing module: {} (Integrity)", module.id);
 
-        let has_content = defs::BUILTIN_PARTITIONS.iter().any(|p| {
-            let part_path = module.source_path.join(p);
+        return sync_module(module, dst);
+    }
 
-            part_path.exists() && has_files_recursive(&part_path)
-        });
+    match apply_delta(&module.source_path, dst, &manifest, verify_content) {
+        Ok(delta) if delta.is_empty() => {
+            log::debug!("Skipping module: {}", module.id);
 
-        if has_content && should_sync(&module.source_path, &dst) {
-            log::info!("Syncing module: {} (Updated/New)", module.id);
+            false
+        }
+        Ok(delta) => {
+            log::info!(
+                "Syncing module: {} ({} added, {} changed, {} removed)",
+                module.id,
+                delta.added,
+                delta.changed,
+                delta.removed
+            );
 
-            if dst.exists()
-                && let Err(e) = fs::remove_dir_all(&dst)
-            {
-                log::warn!("Failed to clean target dir for {}: {}", module.id, e);
+            delta.report.log_summary(&module.id);
+
+            finish_module(module, dst);
+
+            true
+        }
+        Err(e) => {
+            log::warn!(
+                "Incremental sync of {} failed, copying in full: {:#}",
+                module.id,
+                e
+            );
+
+            sync_module(module, dst)
+        }
+    }
+}
+
+fn sync_module(module: &Module, dst: &Path) -> bool {
+    if dst.exists()
+        && let Err(e) = fs::remove_dir_all(dst)
+    {
+        log::warn!("Failed to clean target dir for {}: {}", module.id, e);
+    }
+
+    match utils::sync_dir(&module.source_path, dst) {
+        Ok(report) => report.log_summary(&module.id),
+        Err(e) => {
+            log::error!("Failed to sync module {}: {}", module.id, e);
+
+            return false;
+        }
+    }
+
+    finish_module(module, dst);
+
+    true
+}
+
+fn finish_module(module: &Module, dst: &Path) {
+    dedup::unshare_tree(dst);
+
+    repair_module_contexts(dst, &module.id);
+
+    if let Some(manifest) = AttrManifest::load(&module.source_path) {
+        manifest.apply_tree(dst, &module.id);
+    }
+}
+
+#[derive(Debug, Default)]
+
+struct SyncDelta {
+    added: usize,
+    changed: usize,
+    removed: usize,
+    report: utils::CopyReport,
+}
+
+impl SyncDelta {
+    fn is_empty(&self) -> bool {
+        self.added == 0 && self.changed == 0 && self.removed == 0
+    }
+}
+
+fn is_unchanged(
+    entry: &integrity::ManifestEntry,
+    src: &Path,
+    meta: &fs::Metadata,
+    verify_content: bool,
+) -> bool {
+    let file_type = meta.file_type();
+
+    if entry.source_mode != meta.mode()
+        || entry.source_uid != meta.uid()
+        || entry.source_gid != meta.gid()
+    {
+        return false;
+    }
+
+    if file_type.is_dir() {
+        return entry.kind == integrity::EntryKind::Dir;
+    }
+
+    if file_type.is_symlink() {
+        return entry.kind == integrity::EntryKind::Symlink
+            && fs::read_link(src)
+                .is_ok_and(|target| entry.target.as_deref() == Some(&*target.to_string_lossy()));
+    }
+
+    if !file_type.is_file() {
+        return entry.kind == integrity::EntryKind::Other
+            && entry.source_mtime == meta.mtime()
+            && entry.source_mtime_nsec == meta.mtime_nsec();
+    }
+
+    if entry.kind != integrity::EntryKind::File
+        || entry.source_size != meta.len()
+        || entry.source_mtime != meta.mtime()
+        || entry.source_mtime_nsec != meta.mtime_nsec()
+    {
+        return false;
+    }
+
+    !verify_content
+        || utils::sha256_file(src).is_ok_and(|digest| {
+            let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+
+            entry.sha256.as_deref() == Some(hex.as_str())
+        })
+}
+
+fn remove_entry(path: &Path) -> Result<()> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+
+    if meta.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+fn replace_entry(
+    copier: &mut utils::TreeCopier,
+    src: &Path,
+    dst: &Path,
+    meta: &fs::Metadata,
+) -> Result<()> {
+    if meta.is_dir() {
+        remove_entry(dst)?;
+
+        return copier.copy_dir(src, dst);
+    }
+
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+
+    let staging = dst.with_file_name(format!(".{}.sync", name));
+
+    remove_entry(&staging)?;
+
+    copier.copy_entry(src, &staging)?;
+
+    if fs::symlink_metadata(dst).is_ok_and(|m| m.is_dir()) {
+        fs::remove_dir_all(dst)?;
+    }
+
+    fs::rename(&staging, dst).inspect_err(|_| {
+        let _ = fs::remove_file(&staging);
+    })?;
+
+    copier.relocate(meta, dst);
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+enum Action {
+    Keep,
+    Refresh,
+    Replace,
+    Add,
+}
+
+fn link_identity(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    (!meta.is_dir() && meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+fn apply_delta(
+    src_root: &Path,
+    dst_root: &Path,
+    manifest: &integrity::Manifest,
+    verify_content: bool,
+) -> Result<SyncDelta> {
+    let known: HashMap<&str, &integrity::ManifestEntry> = manifest
+        .entries
+        .iter()
+        .map(|e| (e.path.as_str(), e))
+        .collect();
+
+    let mut delta = SyncDelta::default();
+
+    let mut present = HashSet::new();
+
+    let mut planned = Vec::new();
+
+    let mut touched_links = HashSet::new();
+
+    let mut walker = WalkDir::new(src_root)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter();
+
+    while let Some(entry) = walker.next() {
+        let entry = entry?;
+
+        let relative = entry
+            .path()
+            .strip_prefix(src_root)?
+            .to_string_lossy()
+            .to_string();
+
+        let meta = entry.metadata()?;
+
+        let action = match known.get(relative.as_str()) {
+            Some(known) if is_unchanged(known, entry.path(), &meta, verify_content) => Action::Keep,
+            Some(known) if meta.is_dir() && known.kind == integrity::EntryKind::Dir => {
+                Action::Refresh
+            }
+            Some(_) => Action::Replace,
+            None => Action::Add,
+        };
+
+        if matches!(action, Action::Replace | Action::Add) {
+            if meta.is_dir() {
+                walker.skip_current_dir();
+
+                touched_links.extend(
+                    WalkDir::new(entry.path())
+                        .follow_links(false)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|e| e.metadata().ok())
+                        .filter_map(|m| link_identity(&m)),
+                );
+            } else if let Some(identity) = link_identity(&meta) {
+                touched_links.insert(identity);
             }
+        }
+
+        present.insert(relative.clone());
 
-            if let Err(e) = utils::sync_dir(&module.source_path, &dst) {
-                log::error!("Failed to sync module {}: {}", module.id, e);
-            } else {
-                repair_module_contexts(&dst, &module.id);
+        planned.push((relative, entry.into_path(), meta, action));
+    }
+
+    let mut copier = utils::TreeCopier::default();
+
+    let mut refreshed = Vec::new();
+
+    for (relative, src, meta, action) in planned {
+        let action = match action {
+            Action::Keep if link_identity(&meta).is_some_and(|id| touched_links.contains(&id)) => {
+                Action::Replace
             }
-        } else {
-            log::debug!("Skipping module: {}", module.id);
+            action => action,
+        };
+
+        let dst = dst_root.join(&relative);
+
+        match action {
+            Action::Keep => {}
+            Action::Refresh => refreshed.push((relative, src, dst)),
+            Action::Replace => {
+                replace_entry(&mut copier, &src, &dst, &meta)
+                    .with_context(|| format!("Failed to update {}", relative))?;
+
+                delta.changed += 1;
+            }
+            Action::Add => {
+                replace_entry(&mut copier, &src, &dst, &meta)
+                    .with_context(|| format!("Failed to add {}", relative))?;
+
+                delta.added += 1;
+            }
+        }
+    }
+
+    for (relative, src, dst) in refreshed.into_iter().rev() {
+        copier
+            .copy_metadata(&src, &dst)
+            .with_context(|| format!("Failed to update {}", relative))?;
+
+        delta.changed += 1;
+    }
+
+    delta.report = copier.finish();
+
+    let mut stale: Vec<&str> = known
+        .keys()
+        .copied()
+        .filter(|path| !present.contains(*path))
+        .collect();
+
+    stale.sort_unstable_by(|a, b| b.cmp(a));
+
+    for path in stale {
+        if Path::new(path).parent().is_some_and(|parent| {
+            !parent.as_os_str().is_empty() && !present.contains(&*parent.to_string_lossy())
+        }) {
+            continue;
         }
+
+        remove_entry(&dst_root.join(path)).with_context(|| format!("Failed to remove {}", path))?;
+
+        delta.removed += 1;
+    }
+
+    Ok(delta)
+}
+
+fn write_manifests(modules: &[&Module], target_base: &Path) {
+    modules.par_iter().for_each(|module| {
+        integrity::write_manifest(&target_base.join(&module.id), module);
     });
+}
 
-    Ok(())
+fn is_intact(dst: &Path, module_id: &str) -> bool {
+    let report = integrity::verify(dst, integrity::Depth::Quick);
+
+    if !report.is_ok() {
+        log::warn!(
+            "Storage copy of {} failed integrity check: {}",
+            module_id,
+            report.summary()
+        );
+    }
+
+    report.is_ok()
+}
+
+fn has_content(module: &Module) -> bool {
+    defs::BUILTIN_PARTITIONS.iter().any(|p| {
+        let part_path = module.source_path.join(p);
+
+        part_path.exists() && has_files_recursive(&part_path)
+    })
+}
+
+pub fn is_storage_candidate(module: &Module) -> bool {
+    !matches!(module.rules.default_mode, MountMode::Magic) && has_content(module)
+}
+
+fn packed_entries(module: &Module) -> impl Iterator<Item = walkdir::DirEntry> {
+    WalkDir::new(&module.source_path)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+}
+
+pub fn estimate_payload(modules: &[Module]) -> u64 {
+    const BLOCK: u64 = 4096;
+
+    modules
+        .par_iter()
+        .filter(|m| is_storage_candidate(m))
+        .map(|module| {
+            WalkDir::new(&module.source_path)
+                .follow_links(false)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.metadata().ok())
+                .map(|meta| {
+                    if meta.is_file() {
+                        meta.len().div_ceil(BLOCK) * BLOCK
+                    } else if meta.is_dir() {
+                        BLOCK
+                    } else {
+                        0
+                    }
+                })
+                .sum::<u64>()
+        })
+        .sum()
+}
+
+pub fn content_fingerprint(modules: &[Module], options: &ErofsOptions) -> String {
+    let mut candidates: Vec<&Module> = modules.iter().filter(|m| is_storage_candidate(m)).collect();
+
+    candidates.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut hasher = Sha256::new();
+
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+
+    if let Ok(encoded) = serde_json::to_string(options) {
+        hasher.update(encoded.as_bytes());
+    }
+
+    for prop in ["ro.build.fingerprint", "ro.vendor.build.fingerprint"] {
+        if let Some(value) = utils::getprop(prop) {
+            hasher.update(prop.as_bytes());
+
+            hasher.update(value.as_bytes());
+        }
+    }
+
+    for module in candidates {
+        hasher.update(b"\0module\0");
+
+        hasher.update(module.id.as_bytes());
+
+        for entry in packed_entries(module) {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+
+            let relative = entry
+                .path()
+                .strip_prefix(&module.source_path)
+                .unwrap_or(entry.path());
+
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+
+            hasher.update(
+                format!(
+                    "|{:o}|{}|{}|{}|{}.{}|{}",
+                    meta.mode(),
+                    meta.uid(),
+                    meta.gid(),
+                    meta.len(),
+                    meta.mtime(),
+                    meta.mtime_nsec(),
+                    meta.nlink()
+                )
+                .as_bytes(),
+            );
+
+            for (name, value) in read_xattrs(entry.path()) {
+                hasher.update(name.as_bytes());
+
+                hasher.update(&value);
+            }
+
+            if entry.path_is_symlink()
+                && let Ok(target) = fs::read_link(entry.path())
+            {
+                hasher.update(target.as_os_str().as_encoded_bytes());
+            }
+        }
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn pack_erofs(
+    modules: &[Module],
+    image_path: &Path,
+    compression: Compression,
+    timestamp: Option<u64>,
+) -> Result<BuildStats> {
+    log::info!("Packing EROFS image natively: {}", image_path.display());
+
+    let mut writer = ErofsWriter::new(compression);
+
+    if let Some(timestamp) = timestamp {
+        writer.set_timestamp(timestamp as i64);
+    }
+
+    writer.root_meta_mut().xattrs.insert(
+        selinux::SELINUX_XATTR.to_string(),
+        selinux::DEFAULT_CONTEXT.as_bytes().to_vec(),
+    );
+
+    for module in modules.iter().filter(|m| is_storage_candidate(m)) {
+        log::debug!("Packing module: {}", module.id);
+
+        let manifest = AttrManifest::load(&module.source_path);
+
+        writer
+            .add_tree(&module.id, &module.source_path, |relative, meta| {
+                label_packed_entry(&module.source_path, relative, meta);
+
+                if let Some(attrs) = manifest.as_ref().and_then(|m| m.resolve(relative))
+                    && let Err(e) = attrs.apply_to_meta(meta)
+                {
+                    log::warn!("Attributes {}/{}: {:#}", module.id, relative.display(), e);
+                }
+            })
+            .with_context(|| format!("Failed to pack module {}", module.id))?;
+    }
+
+    let stats = writer.write(image_path)?;
+
+    log::info!(
+        "EROFS image built: {} inodes, {} blocks, {} compressed, {} shared ({} bytes saved), {} hardlinks",
+        stats.inodes,
+        stats.blocks,
+        stats.compressed_files,
+        stats.shared_files,
+        stats.shared_bytes,
+        stats.hardlinks
+    );
+
+    if stats.dropped_xattrs > 0 {
+        log::warn!(
+            "{} xattrs could not be represented in the image",
+            stats.dropped_xattrs
+        );
+    }
+
+    let _ = fs::set_permissions(image_path, fs::Permissions::from_mode(0o644));
+
+    selinux::lsetfilecon(image_path, "u:object_r:ksu_file:s0")?;
+
+    Ok(stats)
+}
+
+fn label_packed_entry(module_root: &Path, relative: &Path, meta: &mut EntryMeta) {
+    let in_partition = relative
+        .components()
+        .next()
+        .and_then(|c| c.as_os_str().to_str())
+        .is_some_and(|first| defs::BUILTIN_PARTITIONS.contains(&first));
+
+    let mirrored = if in_partition {
+        selinux::mirror_context(
+            &module_root.join(relative),
+            module_root,
+            Path::new("/"),
+            true,
+        )
+    } else {
+        None
+    };
+
+    match mirrored {
+        Some(context) => {
+            meta.xattrs
+                .insert(selinux::SELINUX_XATTR.to_string(), context.into_bytes());
+        }
+        None => {
+            meta.xattrs
+                .entry(selinux::SELINUX_XATTR.to_string())
+                .or_insert_with(|| selinux::DEFAULT_CONTEXT.as_bytes().to_vec());
+        }
+    }
 }
 
 fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()> {
@@ -85,72 +695,30 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
     Ok(())
 }
 
-fn should_sync(src: &Path, dst: &Path) -> bool {
-    if !dst.exists() {
-        return true;
-    }
-
-    let src_prop = src.join("module.prop");
-
-    let dst_prop = dst.join("module.prop");
-
-    if !src_prop.exists() || !dst_prop.exists() {
-        return true;
-    }
-
-    match (fs::read(&src_prop), fs::read(&dst_prop)) {
-        (Ok(s), Ok(d)) => s != d,
-        _ => true,
-    }
-}
-
 fn repair_module_contexts(module_root: &Path, module_id: &str) {
+    let mut report = selinux::LabelReport::default();
+
     for part in defs::BUILTIN_PARTITIONS {
         let part_root = module_root.join(part);
 
-        if part_root.exists()
-            && let Err(e) = recursive_context_repair(module_root, &part_root)
-        {
-            log::warn!("Context repair failed for {}/{}: {}", module_id, part, e);
+        if !part_root.exists() {
+            continue;
         }
-    }
-}
 
-fn recursive_context_repair(base: &Path, current: &Path) -> Result<()> {
-    if !current.exists() {
-        return Ok(());
-    }
-
-    let file_name = current.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        report.merge(selinux::relabel_tree(&part_root, |path, _| {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
 
-    if (file_name == "upperdir" || file_name == "workdir")
-        && let Some(parent) = current.parent()
-        && let Ok(ctx) = utils::lgetfilecon(parent)
-    {
-        let _ = utils::lsetfilecon(current, &ctx);
-    } else {
-        let relative = current.strip_prefix(base)?;
-
-        let system_path = Path::new("/").join(relative);
-
-        if system_path.exists() {
-            let _ = utils::copy_path_context(&system_path, current);
-        } else if let Some(parent) = system_path.parent()
-            && parent.exists()
-        {
-            let _ = utils::copy_path_context(parent, current);
-        }
-    }
+            if file_name == "upperdir" || file_name == "workdir" {
+                return path
+                    .parent()
+                    .and_then(|parent| selinux::lgetfilecon(parent).ok());
+            }
 
-    if current.is_dir()
-        && let Ok(entries) = fs::read_dir(current)
-    {
-        for entry in entries.flatten() {
-            let _ = recursive_context_repair(base, &entry.path());
-        }
+            selinux::mirror_context(path, module_root, Path::new("/"), true)
+        }));
     }
 
-    Ok(())
+    report.log_summary(module_id);
 }
 
 fn has_files_recursive(path: &Path) -> bool {
@@ -17,6 +17,10 @@ pub const REMOVE_FILE_NAME: &str = "remove";
 
 pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
 
+pub const ATTRS_FILE_NAME: &str = "hybrid_attrs.json";
+
+pub const INTEGRITY_FILE_NAME: &str = ".hybrid_integrity.json";
+
 pub const OVERLAY_SOURCE: &str = "KSU";
 
 pub const KSU_OVERLAY_SOURCE: &str = OVERLAY_SOURCE;
@@ -29,6 +33,8 @@ pub const MODULE_PROP_FILE: &str = "/data/adb/modules/meta-hybrid/module.prop";
 
 pub const MODULES_DIR: &str = "/data/adb/modules";
 
+pub const RULES_DIR: &str = "/data/adb/meta-hybrid/rules";
+
 pub const BUILTIN_PARTITIONS: &[&str] = &[
     "system",
     "vendor",
@@ -0,0 +1,195 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io::Read;
+
+use anyhow::{Context, Result};
+
+use super::format::{
+    BLOCK_SIZE, LCLUSTER_INDEX_SIZE, LclusterType, lcluster_index, nonhead_deltas,
+};
+
+const MAX_EXTENT: usize = 16 * BLOCK_SIZE;
+
+const SEARCH_STEP: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+
+pub struct Extent {
+    pub start: usize,
+    pub plain: bool,
+}
+
+fn fit(data: &[u8]) -> Option<Vec<u8>> {
+    let compressed = lz4_flex::block::compress(data);
+
+    (compressed.len() <= BLOCK_SIZE).then_some(compressed)
+}
+
+fn fit_extent(data: &[u8]) -> Option<(usize, Vec<u8>)> {
+    if data.len() <= BLOCK_SIZE {
+        return None;
+    }
+
+    let max = data.len().min(MAX_EXTENT);
+
+    if let Some(compressed) = fit(&data[..max]) {
+        return Some((max, compressed));
+    }
+
+    let mut low = BLOCK_SIZE;
+
+    let mut high = max;
+
+    let mut best = None;
+
+    while high - low > SEARCH_STEP {
+        let mid = low + (high - low) / 2;
+
+        match fit(&data[..mid]) {
+            Some(compressed) => {
+                best = Some((mid, compressed));
+
+                low = mid;
+            }
+            None => high = mid,
+        }
+    }
+
+    best
+}
+
+fn fill<R: Read>(reader: &mut R, window: &mut Vec<u8>, remaining: &mut u64) -> Result<()> {
+    let want = (MAX_EXTENT - window.len()).min(*remaining as usize);
+
+    if want == 0 {
+        return Ok(());
+    }
+
+    let start = window.len();
+
+    window.resize(start + want, 0);
+
+    reader
+        .read_exact(&mut window[start..])
+        .context("File changed while packing")?;
+
+    *remaining -= want as u64;
+
+    Ok(())
+}
+
+pub fn compress_lz4<R, F>(reader: &mut R, size: u64, mut emit: F) -> Result<Option<Vec<Extent>>>
+where
+    R: Read,
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let limit = size.div_ceil(BLOCK_SIZE as u64) as usize;
+
+    let mut extents = Vec::new();
+
+    let mut block = vec![0u8; BLOCK_SIZE];
+
+    let mut window = Vec::with_capacity(MAX_EXTENT);
+
+    let mut remaining = size;
+
+    let mut pos = 0;
+
+    fill(reader, &mut window, &mut remaining)?;
+
+    while !window.is_empty() {
+        if extents.len() + 1 >= limit {
+            return Ok(None);
+        }
+
+        block.fill(0);
+
+        let consumed = match fit_extent(&window) {
+            Some((consumed, compressed)) => {
+                block[BLOCK_SIZE - compressed.len()..].copy_from_slice(&compressed);
+
+                extents.push(Extent {
+                    start: pos,
+                    plain: false,
+                });
+
+                consumed
+            }
+            None => {
+                let consumed = window.len().min(BLOCK_SIZE);
+
+                block[..consumed].copy_from_slice(&window[..consumed]);
+
+                extents.push(Extent {
+                    start: pos,
+                    plain: true,
+                });
+
+                consumed
+            }
+        };
+
+        emit(&block)?;
+
+        window.drain(..consumed);
+
+        pos += consumed;
+
+        fill(reader, &mut window, &mut remaining)?;
+    }
+
+    Ok(Some(extents))
+}
+
+pub fn lcluster_indexes(size: usize, extents: &[Extent], blkaddr: u32) -> Vec<u8> {
+    let count = size.div_ceil(BLOCK_SIZE);
+
+    let mut heads = vec![None; count];
+
+    for (i, extent) in extents.iter().enumerate() {
+        heads[extent.start / BLOCK_SIZE] = Some(i);
+    }
+
+    let mut raw = Vec::with_capacity(count * LCLUSTER_INDEX_SIZE);
+
+    let mut head_lcn = 0;
+
+    for lcn in 0..count {
+        if let Some(i) = heads[lcn] {
+            let extent = &extents[i];
+
+            let kind = if extent.plain {
+                LclusterType::Plain
+            } else {
+                LclusterType::Head
+            };
+
+            raw.extend_from_slice(&lcluster_index(
+                kind,
+                (extent.start % BLOCK_SIZE) as u16,
+                blkaddr + i as u32,
+            ));
+
+            head_lcn = lcn;
+        } else if lcn + 1 == count && !size.is_multiple_of(BLOCK_SIZE) {
+            raw.extend_from_slice(&lcluster_index(
+                LclusterType::Plain,
+                (size % BLOCK_SIZE) as u16,
+                0,
+            ));
+        } else {
+            let next = (lcn + 1..count)
+                .find(|n| heads[*n].is_some())
+                .unwrap_or(count);
+
+            raw.extend_from_slice(&lcluster_index(
+                LclusterType::NonHead,
+                0,
+                nonhead_deltas((lcn - head_lcn) as u16, (next - lcn) as u16),
+            ));
+        }
+    }
+
+    raw
+}
@@ -0,0 +1,252 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+pub const BLOCK_BITS: u8 = 12;
+
+pub const BLOCK_SIZE: usize = 1 << BLOCK_BITS;
+
+pub const SUPER_OFFSET: usize = 1024;
+
+pub const SUPER_MAGIC: u32 = 0xE0F5_E1E2;
+
+pub const INODE_SLOT_BITS: u32 = 5;
+
+pub const INODE_SLOT: usize = 1 << INODE_SLOT_BITS;
+
+pub const EXTENDED_INODE_SIZE: usize = 64;
+
+pub const XATTR_IBODY_HEADER_SIZE: usize = 12;
+
+pub const XATTR_ENTRY_SIZE: usize = 4;
+
+pub const DIRENT_SIZE: usize = 12;
+
+pub const LCLUSTER_INDEX_SIZE: usize = 8;
+
+pub const MAP_HEADER_SIZE: usize = 8;
+
+pub const MAP_HEADER_RESERVED: usize = 8;
+
+pub const FEATURE_INCOMPAT_ZERO_PADDING: u32 = 0x0000_0001;
+
+pub const FT_REG_FILE: u8 = 1;
+
+pub const FT_DIR: u8 = 2;
+
+pub const FT_CHRDEV: u8 = 3;
+
+pub const FT_BLKDEV: u8 = 4;
+
+pub const FT_FIFO: u8 = 5;
+
+pub const FT_SOCK: u8 = 6;
+
+pub const FT_SYMLINK: u8 = 7;
+
+const XATTR_PREFIXES: [(u8, &str); 5] = [
+    (2, "system.posix_acl_access"),
+    (3, "system.posix_acl_default"),
+    (1, "user."),
+    (4, "trusted."),
+    (6, "security."),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+pub enum DataLayout {
+    FlatPlain = 0,
+    CompressedFull = 1,
+    FlatInline = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+pub enum LclusterType {
+    Plain = 0,
+    Head = 1,
+    NonHead = 2,
+}
+
+#[derive(Debug, Clone, Default)]
+
+pub struct SuperBlock {
+    pub root_nid: u16,
+    pub inos: u64,
+    pub build_time: u64,
+    pub build_time_nsec: u32,
+    pub blocks: u32,
+    pub meta_blkaddr: u32,
+    pub feature_incompat: u32,
+    pub volume_name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+
+pub struct InodeHeader {
+    pub layout: u16,
+    pub xattr_icount: u16,
+    pub mode: u16,
+    pub size: u64,
+    pub raw: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
+    pub mtime_nsec: u32,
+    pub nlink: u32,
+}
+
+pub fn align(value: usize, to: usize) -> usize {
+    value.div_ceil(to) * to
+}
+
+pub fn file_type(mode: u32) -> u8 {
+    match mode & 0o170000 {
+        0o100000 => FT_REG_FILE,
+        0o040000 => FT_DIR,
+        0o020000 => FT_CHRDEV,
+        0o060000 => FT_BLKDEV,
+        0o010000 => FT_FIFO,
+        0o140000 => FT_SOCK,
+        0o120000 => FT_SYMLINK,
+        _ => 0,
+    }
+}
+
+pub fn encode_rdev(major: u32, minor: u32) -> u32 {
+    (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12)
+}
+
+pub fn split_xattr_name(name: &str) -> Option<(u8, &str)> {
+    XATTR_PREFIXES.iter().find_map(|(index, prefix)| {
+        let suffix = name.strip_prefix(prefix)?;
+
+        (!suffix.is_empty() || prefix.starts_with("system.")).then_some((*index, suffix))
+    })
+}
+
+pub fn xattr_ibody(xattrs: &[(u8, &str, &[u8])]) -> Vec<u8> {
+    if xattrs.is_empty() {
+        return Vec::new();
+    }
+
+    let mut body = vec![0u8; XATTR_IBODY_HEADER_SIZE];
+
+    for (index, suffix, value) in xattrs {
+        body.push(suffix.len() as u8);
+
+        body.push(*index);
+
+        body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+
+        body.extend_from_slice(suffix.as_bytes());
+
+        body.extend_from_slice(value);
+
+        body.resize(align(body.len(), XATTR_ENTRY_SIZE), 0);
+    }
+
+    body
+}
+
+pub fn xattr_icount(ibody_size: usize) -> u16 {
+    if ibody_size == 0 {
+        0
+    } else {
+        (1 + (ibody_size - XATTR_IBODY_HEADER_SIZE) / XATTR_ENTRY_SIZE) as u16
+    }
+}
+
+pub fn dirent(nid: u64, nameoff: u16, file_type: u8) -> [u8; DIRENT_SIZE] {
+    let mut raw = [0u8; DIRENT_SIZE];
+
+    raw[0..8].copy_from_slice(&nid.to_le_bytes());
+
+    raw[8..10].copy_from_slice(&nameoff.to_le_bytes());
+
+    raw[10] = file_type;
+
+    raw
+}
+
+pub fn map_header() -> [u8; MAP_HEADER_SIZE] {
+    [0u8; MAP_HEADER_SIZE]
+}
+
+pub fn lcluster_index(kind: LclusterType, clusterofs: u16, raw: u32) -> [u8; LCLUSTER_INDEX_SIZE] {
+    let mut index = [0u8; LCLUSTER_INDEX_SIZE];
+
+    index[0..2].copy_from_slice(&(kind as u16).to_le_bytes());
+
+    index[2..4].copy_from_slice(&clusterofs.to_le_bytes());
+
+    index[4..8].copy_from_slice(&raw.to_le_bytes());
+
+    index
+}
+
+pub fn nonhead_deltas(delta0: u16, delta1: u16) -> u32 {
+    delta0 as u32 | ((delta1 as u32) << 16)
+}
+
+impl SuperBlock {
+    pub fn to_bytes(&self) -> [u8; 128] {
+        let mut raw = [0u8; 128];
+
+        raw[0..4].copy_from_slice(&SUPER_MAGIC.to_le_bytes());
+
+        raw[12] = BLOCK_BITS;
+
+        raw[14..16].copy_from_slice(&self.root_nid.to_le_bytes());
+
+        raw[16..24].copy_from_slice(&self.inos.to_le_bytes());
+
+        raw[24..32].copy_from_slice(&self.build_time.to_le_bytes());
+
+        raw[32..36].copy_from_slice(&self.build_time_nsec.to_le_bytes());
+
+        raw[36..40].copy_from_slice(&self.blocks.to_le_bytes());
+
+        raw[40..44].copy_from_slice(&self.meta_blkaddr.to_le_bytes());
+
+        let name = self.volume_name.as_bytes();
+
+        let len = name.len().min(16);
+
+        raw[64..64 + len].copy_from_slice(&name[..len]);
+
+        raw[80..84].copy_from_slice(&self.feature_incompat.to_le_bytes());
+
+        raw
+    }
+}
+
+impl InodeHeader {
+    pub fn to_bytes(&self) -> [u8; EXTENDED_INODE_SIZE] {
+        let mut raw = [0u8; EXTENDED_INODE_SIZE];
+
+        raw[0..2].copy_from_slice(&(1 | (self.layout << 1)).to_le_bytes());
+
+        raw[2..4].copy_from_slice(&self.xattr_icount.to_le_bytes());
+
+        raw[4..6].copy_from_slice(&self.mode.to_le_bytes());
+
+        raw[8..16].copy_from_slice(&self.size.to_le_bytes());
+
+        raw[16..20].copy_from_slice(&self.raw.to_le_bytes());
+
+        raw[20..24].copy_from_slice(&self.ino.to_le_bytes());
+
+        raw[24..28].copy_from_slice(&self.uid.to_le_bytes());
+
+        raw[28..32].copy_from_slice(&self.gid.to_le_bytes());
+
+        raw[32..40].copy_from_slice(&self.mtime.to_le_bytes());
+
+        raw[40..44].copy_from_slice(&self.mtime_nsec.to_le_bytes());
+
+        raw[44..48].copy_from_slice(&self.nlink.to_le_bytes());
+
+        raw
+    }
+}
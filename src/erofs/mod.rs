@@ -0,0 +1,813 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod compress;
+mod format;
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, Read},
+    ops::Range,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{FileExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use extattr::{lgetxattr, llistxattr};
+
+use compress::Extent;
+use format::{
+    BLOCK_SIZE, DIRENT_SIZE, DataLayout, EXTENDED_INODE_SIZE, FEATURE_INCOMPAT_ZERO_PADDING,
+    INODE_SLOT, INODE_SLOT_BITS, InodeHeader, LCLUSTER_INDEX_SIZE, MAP_HEADER_SIZE, SUPER_OFFSET,
+    SuperBlock,
+};
+
+const S_IFMT: u32 = 0o170000;
+
+const S_IFDIR: u32 = 0o040000;
+
+const ROOT: usize = 0;
+
+const COPY_CHUNK: usize = 64 * BLOCK_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+#[derive(Debug, Clone, Default)]
+
+pub struct EntryMeta {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+
+pub struct BuildStats {
+    pub inodes: usize,
+    pub blocks: u32,
+    pub compressed_files: usize,
+    pub shared_files: usize,
+    pub shared_bytes: u64,
+    pub hardlinks: usize,
+    pub dropped_xattrs: usize,
+}
+
+enum Payload {
+    Directory(BTreeMap<Vec<u8>, usize>),
+    File(PathBuf),
+    Symlink(Vec<u8>),
+    Special(u32),
+}
+
+struct Node {
+    meta: EntryMeta,
+    payload: Payload,
+    parent: usize,
+    nlink: u32,
+}
+
+#[derive(Clone)]
+
+enum Placement {
+    Empty,
+    Plain(u32),
+    Inline(u32, Vec<u8>),
+    Compressed(u32, Vec<Extent>),
+}
+
+struct Layout {
+    placement: Placement,
+    size: u64,
+    xattrs: Vec<u8>,
+}
+
+pub struct ErofsWriter {
+    compression: Compression,
+    nodes: Vec<Node>,
+    hardlinks: HashMap<(u64, u64), usize>,
+    timestamp: Option<i64>,
+    stats: BuildStats,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+pub fn read_xattrs(path: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut xattrs = BTreeMap::new();
+
+    let Ok(names) = llistxattr(path) else {
+        return xattrs;
+    };
+
+    for name in names {
+        let Some(key) = name.to_str() else {
+            continue;
+        };
+
+        match lgetxattr(path, &name) {
+            Ok(value) => {
+                xattrs.insert(key.to_string(), value);
+            }
+            Err(e) => log::debug!("Failed to read {} on {}: {}", key, path.display(), e),
+        }
+    }
+
+    xattrs
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+pub fn read_xattrs(_path: &Path) -> BTreeMap<String, Vec<u8>> {
+    BTreeMap::new()
+}
+
+fn now() -> (i64, u32) {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs() as i64, d.subsec_nanos()))
+        .unwrap_or_default()
+}
+
+fn chunk_dirents(entries: &[(&[u8], usize)]) -> (Vec<Range<usize>>, usize) {
+    let mut chunks = Vec::new();
+
+    let mut start = 0;
+
+    let mut used = 0;
+
+    for (i, (name, _)) in entries.iter().enumerate() {
+        let need = DIRENT_SIZE + name.len();
+
+        if used + need > BLOCK_SIZE {
+            chunks.push(start..i);
+
+            start = i;
+
+            used = 0;
+        }
+
+        used += need;
+    }
+
+    chunks.push(start..entries.len());
+
+    (chunks, used)
+}
+
+fn inline_room(xattrs: &[u8]) -> usize {
+    BLOCK_SIZE.saturating_sub(EXTENDED_INODE_SIZE + xattrs.len())
+}
+
+fn index_area(xattrs: &[u8], size: u64) -> usize {
+    let head = EXTENDED_INODE_SIZE + xattrs.len();
+
+    format::align(head, MAP_HEADER_SIZE) - head
+        + MAP_HEADER_SIZE
+        + format::MAP_HEADER_RESERVED
+        + (size as usize).div_ceil(BLOCK_SIZE) * LCLUSTER_INDEX_SIZE
+}
+
+impl ErofsWriter {
+    pub fn new(compression: Compression) -> Self {
+        let (mtime, mtime_nsec) = now();
+
+        let root = Node {
+            meta: EntryMeta {
+                mode: S_IFDIR | 0o755,
+                mtime,
+                mtime_nsec,
+                ..Default::default()
+            },
+            payload: Payload::Directory(BTreeMap::new()),
+            parent: ROOT,
+            nlink: 1,
+        };
+
+        Self {
+            compression,
+            nodes: vec![root],
+            hardlinks: HashMap::new(),
+            timestamp: None,
+            stats: BuildStats::default(),
+        }
+    }
+
+    pub fn root_meta_mut(&mut self) -> &mut EntryMeta {
+        &mut self.nodes[ROOT].meta
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: i64) {
+        self.timestamp = Some(timestamp);
+    }
+
+    pub fn add_tree<F>(&mut self, name: &str, source: &Path, mut customize: F) -> Result<()>
+    where
+        F: FnMut(&Path, &mut EntryMeta),
+    {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            bail!("invalid tree name '{}'", name);
+        }
+
+        self.add_entry(ROOT, name.as_bytes(), source, Path::new(""), &mut customize)
+    }
+
+    fn link(&mut self, parent: usize, name: &[u8], child: usize) -> Result<()> {
+        let Payload::Directory(children) = &mut self.nodes[parent].payload else {
+            bail!("parent is not a directory");
+        };
+
+        if children.insert(name.to_vec(), child).is_some() {
+            bail!("duplicate entry '{}'", String::from_utf8_lossy(name));
+        }
+
+        Ok(())
+    }
+
+    fn add_entry<F>(
+        &mut self,
+        parent: usize,
+        name: &[u8],
+        source: &Path,
+        relative: &Path,
+        customize: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(&Path, &mut EntryMeta),
+    {
+        let metadata = fs::symlink_metadata(source)
+            .with_context(|| format!("Failed to stat {}", source.display()))?;
+
+        let file_type = metadata.file_type();
+
+        let link_key = (metadata.dev(), metadata.ino());
+
+        if !file_type.is_dir()
+            && metadata.nlink() > 1
+            && let Some(&existing) = self.hardlinks.get(&link_key)
+        {
+            self.nodes[existing].nlink += 1;
+
+            self.stats.hardlinks += 1;
+
+            return self.link(parent, name, existing);
+        }
+
+        let mut meta = EntryMeta {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            xattrs: read_xattrs(source),
+        };
+
+        customize(relative, &mut meta);
+
+        meta.mode = (metadata.mode() & S_IFMT) | (meta.mode & !S_IFMT);
+
+        let payload = if file_type.is_dir() {
+            Payload::Directory(BTreeMap::new())
+        } else if file_type.is_file() {
+            Payload::File(source.to_path_buf())
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(source)
+                .with_context(|| format!("Failed to read link {}", source.display()))?;
+
+            Payload::Symlink(target.into_os_string().into_vec())
+        } else {
+            let rdev = metadata.rdev();
+
+            Payload::Special(format::encode_rdev(
+                rustix::fs::major(rdev),
+                rustix::fs::minor(rdev),
+            ))
+        };
+
+        let index = self.nodes.len();
+
+        self.nodes.push(Node {
+            meta,
+            payload,
+            parent,
+            nlink: 1,
+        });
+
+        self.link(parent, name, index)?;
+
+        if !file_type.is_dir() && metadata.nlink() > 1 {
+            self.hardlinks.insert(link_key, index);
+        }
+
+        if file_type.is_dir() {
+            for entry in fs::read_dir(source)
+                .with_context(|| format!("Failed to read {}", source.display()))?
+            {
+                let entry = entry?;
+
+                let file_name = entry.file_name();
+
+                self.add_entry(
+                    index,
+                    file_name.as_bytes(),
+                    &entry.path(),
+                    &relative.join(&file_name),
+                    customize,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dirents(&self, index: usize) -> Vec<(&[u8], usize)> {
+        let Payload::Directory(children) = &self.nodes[index].payload else {
+            return Vec::new();
+        };
+
+        let mut entries: Vec<(&[u8], usize)> = children
+            .iter()
+            .map(|(name, child)| (name.as_slice(), *child))
+            .collect();
+
+        entries.push((b".", index));
+
+        entries.push((b"..", self.nodes[index].parent));
+
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        entries
+    }
+
+    fn encode_xattrs(&mut self, index: usize) -> Vec<u8> {
+        let mut entries = Vec::new();
+
+        for (name, value) in &self.nodes[index].meta.xattrs {
+            match format::split_xattr_name(name) {
+                Some((prefix, suffix)) if suffix.len() <= u8::MAX as usize => {
+                    if value.len() > u16::MAX as usize {
+                        log::warn!("Dropping oversized xattr {}", name);
+
+                        self.stats.dropped_xattrs += 1;
+
+                        continue;
+                    }
+
+                    entries.push((prefix, suffix, value.as_slice()));
+                }
+                _ => {
+                    log::debug!("Dropping unsupported xattr {}", name);
+
+                    self.stats.dropped_xattrs += 1;
+                }
+            }
+        }
+
+        format::xattr_ibody(&entries)
+    }
+
+    pub fn write(mut self, output: &Path) -> Result<BuildStats> {
+        let image = File::create(output)
+            .with_context(|| format!("Failed to create {}", output.display()))?;
+
+        let mut cursor: u32 = 1;
+
+        let mut shared: HashMap<[u8; 32], (Placement, u64)> = HashMap::new();
+
+        let mut layouts = Vec::with_capacity(self.nodes.len());
+
+        for index in 0..self.nodes.len() {
+            let xattrs = self.encode_xattrs(index);
+
+            let room = inline_room(&xattrs);
+
+            let (placement, size) = match &self.nodes[index].payload {
+                Payload::Directory(_) => {
+                    let entries = self.dirents(index);
+
+                    let (chunks, used) = chunk_dirents(&entries);
+
+                    let size = ((chunks.len() - 1) * BLOCK_SIZE + used) as u64;
+
+                    let blkaddr = cursor;
+
+                    let placement = if used < BLOCK_SIZE && used <= room {
+                        cursor += chunks.len() as u32 - 1;
+
+                        Placement::Inline(blkaddr, Vec::new())
+                    } else {
+                        cursor += chunks.len() as u32;
+
+                        Placement::Plain(blkaddr)
+                    };
+
+                    (placement, size)
+                }
+                Payload::File(source) => {
+                    let source = source.clone();
+
+                    let size = fs::metadata(&source)
+                        .with_context(|| format!("Failed to stat {}", source.display()))?
+                        .len();
+
+                    let digest = digest_file(&source, size)?;
+
+                    if let Some((placement, size)) = shared.get(&digest) {
+                        self.stats.shared_files += 1;
+
+                        self.stats.shared_bytes += *size;
+
+                        (placement.clone(), *size)
+                    } else {
+                        let placement =
+                            self.place_file(&image, &mut cursor, &source, size, room)
+                                .with_context(|| format!("Failed to pack {}", source.display()))?;
+
+                        if matches!(placement, Placement::Plain(_) | Placement::Compressed(..)) {
+                            shared.insert(digest, (placement.clone(), size));
+                        }
+
+                        (placement, size)
+                    }
+                }
+                Payload::Symlink(target) => {
+                    let placement = place_flat(
+                        &image,
+                        &mut cursor,
+                        &mut target.as_slice(),
+                        target.len() as u64,
+                        room,
+                    )?;
+
+                    (placement, target.len() as u64)
+                }
+                Payload::Special(_) => (Placement::Empty, 0),
+            };
+
+            layouts.push(Layout {
+                placement,
+                size,
+                xattrs,
+            });
+        }
+
+        let meta_blkaddr = cursor;
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+
+        let mut seen = vec![false; self.nodes.len()];
+
+        let mut queue = VecDeque::from([ROOT]);
+
+        seen[ROOT] = true;
+
+        while let Some(index) = queue.pop_front() {
+            order.push(index);
+
+            if let Payload::Directory(children) = &self.nodes[index].payload {
+                for child in children.values() {
+                    if !seen[*child] {
+                        seen[*child] = true;
+
+                        queue.push_back(*child);
+                    }
+                }
+            }
+        }
+
+        let mut nids = vec![0u64; self.nodes.len()];
+
+        let mut meta_size = 0usize;
+
+        for index in &order {
+            let layout = &layouts[*index];
+
+            let head = EXTENDED_INODE_SIZE
+                + layout.xattrs.len()
+                + match &layout.placement {
+                    Placement::Inline(_, tail) if tail.is_empty() => {
+                        layout.size as usize % BLOCK_SIZE
+                    }
+                    Placement::Inline(_, tail) => tail.len(),
+                    _ => 0,
+                };
+
+            if head <= BLOCK_SIZE && meta_size % BLOCK_SIZE + head > BLOCK_SIZE {
+                meta_size = format::align(meta_size, BLOCK_SIZE);
+            }
+
+            nids[*index] = (meta_size >> INODE_SLOT_BITS) as u64;
+
+            let extra = match &layout.placement {
+                Placement::Compressed(..) => index_area(&layout.xattrs, layout.size),
+                _ => head - EXTENDED_INODE_SIZE - layout.xattrs.len(),
+            };
+
+            meta_size = format::align(
+                meta_size + EXTENDED_INODE_SIZE + layout.xattrs.len() + extra,
+                INODE_SLOT,
+            );
+        }
+
+        for (index, layout) in layouts.iter_mut().enumerate() {
+            if !matches!(self.nodes[index].payload, Payload::Directory(_)) {
+                continue;
+            }
+
+            let entries = self.dirents(index);
+
+            let (chunks, _) = chunk_dirents(&entries);
+
+            let mut blocks = Vec::with_capacity(chunks.len() * BLOCK_SIZE);
+
+            for chunk in &chunks {
+                let start = blocks.len();
+
+                let mut nameoff = chunk.len() * DIRENT_SIZE;
+
+                for (name, child) in &entries[chunk.clone()] {
+                    blocks.extend_from_slice(&format::dirent(
+                        nids[*child],
+                        nameoff as u16,
+                        format::file_type(self.nodes[*child].meta.mode),
+                    ));
+
+                    nameoff += name.len();
+                }
+
+                for (name, _) in &entries[chunk.clone()] {
+                    blocks.extend_from_slice(name);
+                }
+
+                blocks.resize(start + BLOCK_SIZE, 0);
+            }
+
+            blocks.truncate(layout.size as usize);
+
+            match &mut layout.placement {
+                Placement::Plain(blkaddr) => {
+                    write_blocks(&image, *blkaddr, &blocks)?;
+                }
+                Placement::Inline(blkaddr, tail) => {
+                    let full = blocks.len() / BLOCK_SIZE * BLOCK_SIZE;
+
+                    write_blocks(&image, *blkaddr, &blocks[..full])?;
+
+                    *tail = blocks[full..].to_vec();
+                }
+                _ => {}
+            }
+        }
+
+        let mut meta = vec![0u8; meta_size];
+
+        let mut compressed = false;
+
+        for index in &order {
+            let node = &self.nodes[*index];
+
+            let layout = &layouts[*index];
+
+            let offset = (nids[*index] as usize) << INODE_SLOT_BITS;
+
+            let nlink = match &node.payload {
+                Payload::Directory(children) => {
+                    2 + children
+                        .values()
+                        .filter(|c| matches!(self.nodes[**c].payload, Payload::Directory(_)))
+                        .count() as u32
+                }
+                _ => node.nlink,
+            };
+
+            let (data_layout, raw) = match (&layout.placement, &node.payload) {
+                (_, Payload::Special(rdev)) => (DataLayout::FlatPlain, *rdev),
+                (Placement::Empty, _) => (DataLayout::FlatPlain, 0),
+                (Placement::Plain(blkaddr), _) => (DataLayout::FlatPlain, *blkaddr),
+                (Placement::Inline(blkaddr, _), _) => (DataLayout::FlatInline, *blkaddr),
+                (Placement::Compressed(_, extents), _) => {
+                    (DataLayout::CompressedFull, extents.len() as u32)
+                }
+            };
+
+            let header = InodeHeader {
+                layout: data_layout as u16,
+                xattr_icount: format::xattr_icount(layout.xattrs.len()),
+                mode: node.meta.mode as u16,
+                size: layout.size,
+                raw,
+                ino: *index as u32 + 1,
+                uid: node.meta.uid,
+                gid: node.meta.gid,
+                mtime: self.timestamp.unwrap_or(node.meta.mtime) as u64,
+                mtime_nsec: if self.timestamp.is_some() {
+                    0
+                } else {
+                    node.meta.mtime_nsec
+                },
+                nlink,
+            };
+
+            let mut pos = offset;
+
+            meta[pos..pos + EXTENDED_INODE_SIZE].copy_from_slice(&header.to_bytes());
+
+            pos += EXTENDED_INODE_SIZE;
+
+            meta[pos..pos + layout.xattrs.len()].copy_from_slice(&layout.xattrs);
+
+            pos += layout.xattrs.len();
+
+            match &layout.placement {
+                Placement::Inline(_, tail) => {
+                    meta[pos..pos + tail.len()].copy_from_slice(tail);
+                }
+                Placement::Compressed(blkaddr, extents) => {
+                    compressed = true;
+
+                    pos = format::align(pos, MAP_HEADER_SIZE);
+
+                    meta[pos..pos + MAP_HEADER_SIZE].copy_from_slice(&format::map_header());
+
+                    pos += MAP_HEADER_SIZE + format::MAP_HEADER_RESERVED;
+
+                    let indexes =
+                        compress::lcluster_indexes(layout.size as usize, extents, *blkaddr);
+
+                    meta[pos..pos + indexes.len()].copy_from_slice(&indexes);
+                }
+                _ => {}
+            }
+        }
+
+        write_blocks(&image, meta_blkaddr, &meta)?;
+
+        let blocks = meta_blkaddr + meta_size.div_ceil(BLOCK_SIZE) as u32;
+
+        let (build_time, build_time_nsec) = self.timestamp.map(|t| (t, 0)).unwrap_or_else(now);
+
+        let root_nid = u16::try_from(nids[ROOT]).context("root inode out of range")?;
+
+        let superblock = SuperBlock {
+            root_nid,
+            inos: self.nodes.len() as u64,
+            build_time: build_time as u64,
+            build_time_nsec,
+            blocks,
+            meta_blkaddr,
+            feature_incompat: if compressed {
+                FEATURE_INCOMPAT_ZERO_PADDING
+            } else {
+                0
+            },
+            volume_name: "meta-hybrid".to_string(),
+        };
+
+        image.write_all_at(&superblock.to_bytes(), SUPER_OFFSET as u64)?;
+
+        image.set_len(blocks as u64 * BLOCK_SIZE as u64)?;
+
+        image.sync_all()?;
+
+        self.stats.inodes = self.nodes.len();
+
+        self.stats.blocks = blocks;
+
+        Ok(self.stats)
+    }
+
+    fn place_file(
+        &mut self,
+        image: &File,
+        cursor: &mut u32,
+        source: &Path,
+        size: u64,
+        room: usize,
+    ) -> Result<Placement> {
+        if self.compression == Compression::Lz4 && size > BLOCK_SIZE as u64 {
+            let blkaddr = *cursor;
+
+            let mut written = 0;
+
+            let mut reader = File::open(source)?.take(size);
+
+            let extents = compress::compress_lz4(&mut reader, size, |block| {
+                write_blocks(image, blkaddr + written, block)?;
+
+                written += 1;
+
+                Ok(())
+            })?;
+
+            if let Some(extents) = extents {
+                *cursor += extents.len() as u32;
+
+                self.stats.compressed_files += 1;
+
+                return Ok(Placement::Compressed(blkaddr, extents));
+            }
+        }
+
+        let mut reader = File::open(source)?.take(size);
+
+        place_flat(image, cursor, &mut reader, size, room)
+    }
+}
+
+fn digest_file(source: &Path, size: u64) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+
+    let mut reader = File::open(source)
+        .with_context(|| format!("Failed to open {}", source.display()))?
+        .take(size);
+
+    io::copy(&mut reader, &mut hasher)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+
+    Ok(hasher.finalize().into())
+}
+
+fn write_blocks(image: &File, blkaddr: u32, data: &[u8]) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    image
+        .write_all_at(data, blkaddr as u64 * BLOCK_SIZE as u64)
+        .context("Failed to write image data")
+}
+
+fn place_flat<R: Read>(
+    image: &File,
+    cursor: &mut u32,
+    reader: &mut R,
+    size: u64,
+    room: usize,
+) -> Result<Placement> {
+    if size == 0 {
+        return Ok(Placement::Empty);
+    }
+
+    let full = size / BLOCK_SIZE as u64 * BLOCK_SIZE as u64;
+
+    let tail = (size - full) as usize;
+
+    let blkaddr = *cursor;
+
+    let mut buffer = vec![0u8; COPY_CHUNK];
+
+    let mut offset = 0;
+
+    while offset < full {
+        let len = (full - offset).min(COPY_CHUNK as u64) as usize;
+
+        reader
+            .read_exact(&mut buffer[..len])
+            .context("File changed while packing")?;
+
+        image
+            .write_all_at(&buffer[..len], blkaddr as u64 * BLOCK_SIZE as u64 + offset)
+            .context("Failed to write image data")?;
+
+        offset += len as u64;
+    }
+
+    *cursor += (full / BLOCK_SIZE as u64) as u32;
+
+    if tail == 0 {
+        return Ok(Placement::Plain(blkaddr));
+    }
+
+    let mut rest = vec![0u8; tail];
+
+    reader
+        .read_exact(&mut rest)
+        .context("File changed while packing")?;
+
+    if tail <= room {
+        return Ok(Placement::Inline(blkaddr, rest));
+    }
+
+    write_blocks(image, *cursor, &rest)?;
+
+    *cursor += 1;
+
+    Ok(Placement::Plain(blkaddr))
+}
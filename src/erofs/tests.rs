@@ -0,0 +1,372 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt, symlink},
+    path::{Path, PathBuf},
+};
+
+use rustix::{
+    fs::{CWD, FileType, Mode, XattrFlags, lsetxattr, makedev, mknodat},
+    mount::{UnmountFlags, unmount},
+};
+use walkdir::WalkDir;
+
+use super::{
+    Compression, ErofsWriter, chunk_dirents,
+    compress::{Extent, lcluster_indexes},
+    format::{BLOCK_SIZE, SUPER_MAGIC, SuperBlock, split_xattr_name, xattr_ibody, xattr_icount},
+    read_xattrs,
+};
+use crate::utils;
+
+struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = unmount(self.0.join("mnt"), UnmountFlags::DETACH);
+
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn can_mount() -> bool {
+    fs::metadata("/proc/self").is_ok_and(|m| m.uid() == 0)
+        && Path::new("/dev/loop-control").exists()
+        && fs::read_to_string("/proc/filesystems").is_ok_and(|s| s.contains("erofs"))
+}
+
+fn compressible(len: usize) -> Vec<u8> {
+    (0..len).map(|i| b"meta-hybrid erofs "[i % 18]).collect()
+}
+
+fn incompressible(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+
+            state ^= state >> 7;
+
+            state ^= state << 17;
+
+            state as u8
+        })
+        .collect()
+}
+
+fn build_source(root: &Path) {
+    let dir = root.join("system/etc");
+
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o750)).unwrap();
+
+    fs::write(dir.join("small.conf"), b"key=value\n").unwrap();
+
+    fs::write(dir.join("large.bin"), compressible(300 * 1024)).unwrap();
+
+    fs::write(dir.join("random.bin"), incompressible(70 * 1024 + 123)).unwrap();
+
+    fs::write(dir.join("empty"), b"").unwrap();
+
+    fs::hard_link(dir.join("large.bin"), dir.join("large.link")).unwrap();
+
+    fs::hard_link(dir.join("small.conf"), root.join("system/small.link")).unwrap();
+
+    symlink("etc/small.conf", root.join("system/conf")).unwrap();
+
+    symlink("/dev/null", dir.join("absolute")).unwrap();
+
+    mknodat(
+        CWD,
+        dir.join("removed"),
+        FileType::CharacterDevice,
+        Mode::empty(),
+        makedev(0, 0),
+    )
+    .unwrap();
+
+    fs::set_permissions(dir.join("small.conf"), fs::Permissions::from_mode(0o640)).unwrap();
+
+    rustix::fs::chown(
+        dir.join("random.bin"),
+        Some(rustix::fs::Uid::from_raw(1000)),
+        Some(rustix::fs::Gid::from_raw(2000)),
+    )
+    .unwrap();
+
+    lsetxattr(&dir, "trusted.overlay.opaque", b"y", XattrFlags::empty()).unwrap();
+
+    lsetxattr(
+        dir.join("large.bin"),
+        "user.meta-hybrid.test",
+        &compressible(100),
+        XattrFlags::empty(),
+    )
+    .unwrap();
+}
+
+fn compare(source: &Path, mounted: &Path) {
+    let mut checked = 0;
+
+    for entry in WalkDir::new(source).sort_by_file_name() {
+        let entry = entry.unwrap();
+
+        let relative = entry.path().strip_prefix(source).unwrap();
+
+        let ours = fs::symlink_metadata(entry.path()).unwrap();
+
+        let theirs = fs::symlink_metadata(mounted.join(relative))
+            .unwrap_or_else(|e| panic!("{} missing from image: {}", relative.display(), e));
+
+        let name = relative.display();
+
+        assert_eq!(ours.mode(), theirs.mode(), "mode of {}", name);
+
+        assert_eq!(ours.uid(), theirs.uid(), "uid of {}", name);
+
+        assert_eq!(ours.gid(), theirs.gid(), "gid of {}", name);
+
+        assert_eq!(ours.mtime(), theirs.mtime(), "mtime of {}", name);
+
+        assert_eq!(
+            ours.mtime_nsec(),
+            theirs.mtime_nsec(),
+            "mtime_nsec of {}",
+            name
+        );
+
+        assert_eq!(ours.rdev(), theirs.rdev(), "rdev of {}", name);
+
+        if !ours.is_dir() {
+            assert_eq!(ours.len(), theirs.len(), "size of {}", name);
+
+            assert_eq!(ours.nlink(), theirs.nlink(), "nlink of {}", name);
+        }
+
+        if ours.is_file() {
+            assert!(
+                fs::read(entry.path()).unwrap() == fs::read(mounted.join(relative)).unwrap(),
+                "content of {}",
+                name
+            );
+        }
+
+        if ours.file_type().is_symlink() {
+            assert_eq!(
+                fs::read_link(entry.path()).unwrap(),
+                fs::read_link(mounted.join(relative)).unwrap(),
+                "target of {}",
+                name
+            );
+        }
+
+        assert_eq!(
+            read_xattrs(entry.path()),
+            read_xattrs(&mounted.join(relative)),
+            "xattrs of {}",
+            name
+        );
+
+        checked += 1;
+    }
+
+    assert!(checked > 10);
+
+    let etc = mounted.join("system/etc");
+
+    assert_eq!(
+        fs::metadata(etc.join("large.bin")).unwrap().ino(),
+        fs::metadata(etc.join("large.link")).unwrap().ino()
+    );
+
+    assert_eq!(
+        fs::metadata(etc.join("small.conf")).unwrap().ino(),
+        fs::metadata(mounted.join("system/small.link"))
+            .unwrap()
+            .ino()
+    );
+}
+
+#[test]
+
+fn packed_image_mounts_with_identical_contents() {
+    if !can_mount() {
+        eprintln!("skipping: loop-mounting erofs needs root, /dev/loop-control and erofs");
+
+        return;
+    }
+
+    let scratch =
+        Scratch(std::env::temp_dir().join(format!("meta-hybrid-erofs-{}", std::process::id())));
+
+    let source = scratch.0.join("src");
+
+    let image = scratch.0.join("test.img");
+
+    let mount_point = scratch.0.join("mnt");
+
+    build_source(&source.join("module"));
+
+    for compression in [Compression::None, Compression::Lz4] {
+        let mut writer = ErofsWriter::new(compression);
+
+        writer
+            .add_tree("module", &source.join("module"), |_, _| {})
+            .unwrap();
+
+        let stats = writer.write(&image).unwrap();
+
+        assert_eq!(stats.hardlinks, 2);
+
+        assert_eq!(
+            stats.compressed_files > 0,
+            compression == Compression::Lz4,
+            "compressed files with {:?}",
+            compression
+        );
+
+        let device = utils::mount_erofs_image(&image, &mount_point).unwrap();
+
+        compare(&source.join("module"), &mount_point.join("module"));
+
+        unmount(&mount_point, UnmountFlags::DETACH).unwrap();
+
+        let _ = device.detach();
+    }
+}
+
+#[test]
+
+fn lcluster_indexes_mark_heads_nonheads_and_tail() {
+    let extents = [
+        Extent {
+            start: 0,
+            plain: false,
+        },
+        Extent {
+            start: 2 * BLOCK_SIZE + 50,
+            plain: true,
+        },
+    ];
+
+    let raw = lcluster_indexes(3 * BLOCK_SIZE + 100, &extents, 10);
+
+    assert_eq!(
+        raw,
+        [
+            [1, 0, 0, 0, 10, 0, 0, 0],
+            [2, 0, 0, 0, 1, 0, 1, 0],
+            [0, 0, 50, 0, 11, 0, 0, 0],
+            [0, 0, 100, 0, 0, 0, 0, 0],
+        ]
+        .concat()
+    );
+
+    let raw = lcluster_indexes(
+        2 * BLOCK_SIZE,
+        &[Extent {
+            start: 0,
+            plain: false,
+        }],
+        7,
+    );
+
+    assert_eq!(
+        raw,
+        [[1, 0, 0, 0, 7, 0, 0, 0], [2, 0, 0, 0, 1, 0, 1, 0]].concat()
+    );
+}
+
+#[test]
+
+fn chunk_dirents_splits_at_block_boundaries() {
+    let name: &[u8] = &[b'a'; 20];
+
+    let entries = vec![(name, 0); 130];
+
+    assert_eq!(chunk_dirents(&entries), (vec![0..128, 128..130], 64));
+
+    assert_eq!(chunk_dirents(&entries[..128]), (vec![0..128], BLOCK_SIZE));
+
+    assert_eq!(chunk_dirents(&[]), (vec![0..0], 0));
+}
+
+#[test]
+
+fn xattr_ibody_pads_entries_and_counts_slots() {
+    assert!(xattr_ibody(&[]).is_empty());
+
+    assert_eq!(xattr_icount(0), 0);
+
+    assert_eq!(
+        split_xattr_name("trusted.overlay.opaque"),
+        Some((4, "overlay.opaque"))
+    );
+
+    assert_eq!(split_xattr_name("user."), None);
+
+    let body = xattr_ibody(&[(4, "overlay.opaque", b"y"), (1, "a", b"bc")]);
+
+    let mut expected = vec![0u8; 12];
+
+    expected.extend_from_slice(&[14, 4, 1, 0]);
+
+    expected.extend_from_slice(b"overlay.opaquey\0");
+
+    expected.extend_from_slice(&[1, 1, 2, 0]);
+
+    expected.extend_from_slice(b"abc\0");
+
+    assert_eq!(body, expected);
+
+    assert_eq!(xattr_icount(body.len()), 8);
+}
+
+#[test]
+
+fn superblock_fields_land_at_their_offsets() {
+    let raw = SuperBlock {
+        root_nid: 0x1234,
+        inos: 0x0102_0304_0506_0708,
+        build_time: 0x1122_3344_5566_7788,
+        build_time_nsec: 0xdead_beef,
+        blocks: 0x0a0b_0c0d,
+        meta_blkaddr: 1,
+        feature_incompat: 0x0000_0001,
+        volume_name: "meta-hybrid-volume-name".to_string(),
+    }
+    .to_bytes();
+
+    assert_eq!(raw[0..4], SUPER_MAGIC.to_le_bytes());
+
+    assert_eq!(raw[4..12], [0; 8]);
+
+    assert_eq!(raw[12], 12);
+
+    assert_eq!(raw[14..16], [0x34, 0x12]);
+
+    assert_eq!(raw[16..24], [8, 7, 6, 5, 4, 3, 2, 1]);
+
+    assert_eq!(
+        raw[24..32],
+        [0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]
+    );
+
+    assert_eq!(raw[32..36], [0xef, 0xbe, 0xad, 0xde]);
+
+    assert_eq!(raw[36..40], [0x0d, 0x0c, 0x0b, 0x0a]);
+
+    assert_eq!(raw[40..44], [1, 0, 0, 0]);
+
+    assert_eq!(raw[44..64], [0; 20]);
+
+    assert_eq!(&raw[64..80], b"meta-hybrid-volu");
+
+    assert_eq!(raw[80..84], [1, 0, 0, 0]);
+
+    assert!(raw[84..].iter().all(|b| *b == 0));
+}
@@ -4,7 +4,9 @@
 mod conf;
 mod core;
 mod defs;
+mod erofs;
 mod mount;
+mod selinux;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod try_umount;
 mod utils;
@@ -15,7 +17,7 @@ use mimalloc::MiMalloc;
 use std::path::{Path, PathBuf};
 
 use conf::{
-    cli::{Cli, Commands},
+    cli::{Cli, Commands, StorageAction},
     cli_handlers,
     config::{CONFIG_FILE_DEFAULT, Config},
 };
@@ -67,13 +69,30 @@ fn main() -> Result<()> {
             Commands::SaveRules { module, payload } => {
                 cli_handlers::handle_save_rules(module, payload)?
             }
-            Commands::Storage => cli_handlers::handle_storage()?,
+            Commands::Storage { per_module, action } => match action {
+                Some(StorageAction::Bench { text }) => {
+                    cli_handlers::handle_storage_bench(&cli, *text)?
+                }
+                None => cli_handlers::handle_storage(*per_module)?,
+            },
+            Commands::Verify {
+                repair,
+                force,
+                text,
+            } => cli_handlers::handle_verify(&cli, *repair, *force, *text)?,
+            Commands::Resync { module, force } => {
+                cli_handlers::handle_resync(&cli, module, *force)?
+            }
             Commands::Modules => cli_handlers::handle_modules(&cli)?,
             Commands::Conflicts => cli_handlers::handle_conflicts(&cli)?,
             Commands::Diagnostics => cli_handlers::handle_diagnostics(&cli)?,
-            Commands::SystemAction { action, value } => {
-                cli_handlers::handle_system_action(&cli, action, value.as_deref())?
-            }
+            Commands::Capabilities => cli_handlers::handle_capabilities()?,
+            Commands::MagicPreview { text } => cli_handlers::handle_magic_preview(&cli, *text)?,
+            Commands::SystemAction {
+                action,
+                value,
+                text,
+            } => cli_handlers::handle_system_action(&cli, action, value.as_deref(), *text)?,
         }
 
         return Ok(());
@@ -132,6 +151,19 @@ fn main() -> Result<()> {
 
         plan.print_visuals();
 
+        if !plan.magic_module_paths.is_empty() {
+            match executor::preview_magic(&plan, &config) {
+                Ok(preview) => {
+                    log::info!(">> Magic Mount Operations: {}", preview.summary());
+
+                    for op in &preview.operations {
+                        log::info!("   {}", op);
+                    }
+                }
+                Err(e) => log::warn!("Magic mount preview failed: {:#}", e),
+            }
+        }
+
         log::info!(">> Analyzing File Conflicts...");
 
         let report = plan.analyze_conflicts();
@@ -159,7 +191,9 @@ fn main() -> Result<()> {
 
         log::info!(">> Running System Diagnostics...");
 
-        let issues = executor::diagnose_plan(&plan);
+        let mut issues = executor::diagnose_plan(&plan);
+
+        issues.extend(executor::diagnose_propagation(&plan, &config));
 
         let mut critical_count = 0;
 
@@ -212,6 +246,8 @@ fn main() -> Result<()> {
         log::debug!("Kernel Version: {}", version.trim());
     }
 
+    core::capabilities::get().log_summary();
+
     if config.disable_umount {
         log::warn!("!! Umount is DISABLED via config.");
     }
@@ -223,7 +259,7 @@ fn main() -> Result<()> {
 
     let img_path = Path::new(defs::BASE_DIR).join("modules.img");
 
-    if let Err(e) = granary::create_silo(&config, "Boot Backup", "Automatic Pre-Mount") {
+    if let Err(e) = granary::create_boot_silo(&config) {
         log::warn!("Granary: Failed to create boot snapshot: {}", e);
     }
 
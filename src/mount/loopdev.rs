@@ -0,0 +1,248 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{File, OpenOptions},
+    os::fd::{AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use nix::{errno::Errno, ioctl_none_bad, ioctl_write_int_bad, ioctl_write_ptr_bad};
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+
+const LOOP_SET_FD: u32 = 0x4C00;
+
+const LOOP_CLR_FD: u32 = 0x4C01;
+
+const LOOP_SET_STATUS64: u32 = 0x4C04;
+
+const LOOP_SET_DIRECT_IO: u32 = 0x4C08;
+
+const LOOP_CONFIGURE: u32 = 0x4C0A;
+
+const LOOP_CTL_GET_FREE: u32 = 0x4C82;
+
+const LO_FLAGS_READ_ONLY: u32 = 1;
+
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+
+const LO_FLAGS_DIRECT_IO: u32 = 16;
+
+const ATTACH_RETRIES: usize = 8;
+
+#[repr(C)]
+
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; 64],
+    lo_crypt_name: [u8; 64],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+#[repr(C)]
+
+struct LoopConfig {
+    fd: u32,
+    block_size: u32,
+    info: LoopInfo64,
+    reserved: [u64; 8],
+}
+
+ioctl_none_bad!(loop_ctl_get_free, LOOP_CTL_GET_FREE);
+
+ioctl_none_bad!(loop_clr_fd, LOOP_CLR_FD);
+
+ioctl_write_int_bad!(loop_set_fd, LOOP_SET_FD);
+
+ioctl_write_int_bad!(loop_set_direct_io, LOOP_SET_DIRECT_IO);
+
+ioctl_write_ptr_bad!(loop_configure, LOOP_CONFIGURE, LoopConfig);
+
+ioctl_write_ptr_bad!(loop_set_status64, LOOP_SET_STATUS64, LoopInfo64);
+
+#[derive(Debug, Clone, Copy)]
+
+pub struct LoopOptions {
+    pub read_only: bool,
+    pub direct_io: bool,
+}
+
+#[derive(Debug, Clone)]
+
+pub struct LoopDevice {
+    pub path: PathBuf,
+}
+
+fn loop_info(flags: u32, image: &Path) -> LoopInfo64 {
+    let mut info = LoopInfo64 {
+        lo_device: 0,
+        lo_inode: 0,
+        lo_rdevice: 0,
+        lo_offset: 0,
+        lo_sizelimit: 0,
+        lo_number: 0,
+        lo_encrypt_type: 0,
+        lo_encrypt_key_size: 0,
+        lo_flags: flags,
+        lo_file_name: [0; 64],
+        lo_crypt_name: [0; 64],
+        lo_encrypt_key: [0; 32],
+        lo_init: [0; 2],
+    };
+
+    let name = image.as_os_str().as_encoded_bytes();
+
+    let len = name.len().min(info.lo_file_name.len() - 1);
+
+    info.lo_file_name[..len].copy_from_slice(&name[..len]);
+
+    info
+}
+
+fn device_path(number: u32) -> Option<PathBuf> {
+    [
+        format!("/dev/block/loop{}", number),
+        format!("/dev/loop{}", number),
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .find(|p| p.exists())
+}
+
+fn open_device(number: u32, read_only: bool) -> Result<(PathBuf, File)> {
+    for _ in 0..ATTACH_RETRIES {
+        if let Some(path) = device_path(number) {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(!read_only)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+
+            return Ok((path, file));
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    bail!("loop device node {} did not appear", number)
+}
+
+fn configure(device: &File, backing: &File, image: &Path, options: LoopOptions) -> Result<()> {
+    let mut flags = LO_FLAGS_AUTOCLEAR;
+
+    if options.read_only {
+        flags |= LO_FLAGS_READ_ONLY;
+    }
+
+    if options.direct_io {
+        flags |= LO_FLAGS_DIRECT_IO;
+    }
+
+    let config = LoopConfig {
+        fd: backing.as_raw_fd() as u32,
+        block_size: 0,
+        info: loop_info(flags, image),
+        reserved: [0; 8],
+    };
+
+    match unsafe { loop_configure(device.as_raw_fd(), &config) } {
+        Ok(_) => return Ok(()),
+        Err(Errno::EINVAL | Errno::ENOTTY) => {
+            log::debug!("LOOP_CONFIGURE unsupported, falling back to LOOP_SET_FD");
+        }
+        Err(e) => return Err(e).context("LOOP_CONFIGURE failed"),
+    }
+
+    unsafe { loop_set_fd(device.as_raw_fd(), backing.as_raw_fd()) }
+        .context("LOOP_SET_FD failed")?;
+
+    let info = loop_info(LO_FLAGS_AUTOCLEAR, image);
+
+    if let Err(e) = unsafe { loop_set_status64(device.as_raw_fd(), &info) } {
+        let _ = unsafe { loop_clr_fd(device.as_raw_fd()) };
+
+        return Err(e).context("LOOP_SET_STATUS64 failed");
+    }
+
+    if options.direct_io
+        && let Err(e) = unsafe { loop_set_direct_io(device.as_raw_fd(), 1) }
+    {
+        log::debug!("Direct I/O unavailable for {}: {}", image.display(), e);
+    }
+
+    Ok(())
+}
+
+impl LoopDevice {
+    pub fn attach(image: &Path, options: LoopOptions) -> Result<(Self, OwnedFd)> {
+        let backing = OpenOptions::new()
+            .read(true)
+            .write(!options.read_only)
+            .open(image)
+            .with_context(|| format!("Failed to open {}", image.display()))?;
+
+        let control = File::open(LOOP_CONTROL).context("Failed to open loop-control")?;
+
+        let mut last_error = None;
+
+        for _ in 0..ATTACH_RETRIES {
+            let number = unsafe { loop_ctl_get_free(control.as_raw_fd()) }
+                .context("LOOP_CTL_GET_FREE failed")? as u32;
+
+            let (path, device) = open_device(number, options.read_only)?;
+
+            let attempt = configure(&device, &backing, image, options).or_else(|e| {
+                if options.direct_io {
+                    log::debug!("Retrying {} without direct I/O: {:#}", path.display(), e);
+
+                    let buffered = LoopOptions {
+                        direct_io: false,
+                        ..options
+                    };
+
+                    configure(&device, &backing, image, buffered)
+                } else {
+                    Err(e)
+                }
+            });
+
+            match attempt {
+                Ok(_) => {
+                    log::debug!("Attached {} to {}", image.display(), path.display());
+
+                    return Ok((Self { path }, device.into()));
+                }
+                Err(e) if e.root_cause().downcast_ref::<Errno>() == Some(&Errno::EBUSY) => {
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no free loop device")))
+            .with_context(|| format!("Failed to attach {}", image.display()))
+    }
+
+    pub fn detach(&self) -> Result<()> {
+        let device = File::open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+
+        match unsafe { loop_clr_fd(device.as_raw_fd()) } {
+            Ok(_) | Err(Errno::ENXIO) => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to detach {}", self.path.display())),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use std::{
     collections::hash_map::Entry,
     collections::{HashMap, HashSet},
     fs::{self, DirEntry, create_dir, create_dir_all, read_dir, read_link},
+    io,
     os::unix::fs::{MetadataExt, symlink},
     path::{Path, PathBuf},
 };
@@ -12,17 +13,24 @@ use std::{
 use anyhow::{Context, Result, bail};
 use rayon::prelude::*;
 use rustix::{
-    fs::{Gid, Mode, Uid, chmod, chown},
+    fd::{AsFd, AsRawFd, BorrowedFd},
+    fs::{CWD, Gid, Mode, Uid, chmod, chown},
     mount::{
-        MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_bind, mount_change,
-        mount_move, mount_remount, unmount,
+        MountFlags, MountPropagationFlags, MoveMountFlags, OpenTreeFlags, UnmountFlags, mount,
+        mount_bind, mount_change, mount_move, mount_remount, move_mount, open_tree, unmount,
     },
 };
 
 use crate::{
+    conf::config::PropagationMode,
+    core::{attrs::AttrIndex, capabilities},
     defs::{DISABLE_FILE_NAME, REMOVE_FILE_NAME, SKIP_MOUNT_FILE_NAME},
-    mount::node::{Node, NodeFileType},
-    utils::{ensure_dir_exists, lgetfilecon, lsetfilecon},
+    mount::{
+        node::{Node, NodeFileType},
+        propagation,
+    },
+    selinux::{lgetfilecon, lsetfilecon},
+    utils::ensure_dir_exists,
 };
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -30,6 +38,78 @@ use crate::try_umount::send_unmountable;
 
 const ROOT_PARTITIONS: [&str; 4] = ["vendor", "system_ext", "product", "odm"];
 
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+#[repr(C)]
+
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+fn set_mount_attr(
+    fd: BorrowedFd<'_>,
+    attr_set: u64,
+    propagation: MountPropagationFlags,
+) -> io::Result<()> {
+    let attr = MountAttr {
+        attr_set,
+        attr_clr: 0,
+        propagation: propagation.bits() as u64,
+        userns_fd: 0,
+    };
+
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            fd.as_raw_fd(),
+            c"".as_ptr(),
+            libc::AT_EMPTY_PATH,
+            &attr as *const MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    };
+
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn attach_clone(
+    source: &Path,
+    target: &Path,
+    recursive: bool,
+    propagation: MountPropagationFlags,
+) -> Result<()> {
+    let mut flags = OpenTreeFlags::OPEN_TREE_CLONE | OpenTreeFlags::OPEN_TREE_CLOEXEC;
+
+    if recursive {
+        flags |= OpenTreeFlags::AT_RECURSIVE;
+    }
+
+    let clone =
+        open_tree(CWD, source, flags).with_context(|| format!("clone {}", source.display()))?;
+
+    if let Err(e) = set_mount_attr(clone.as_fd(), MOUNT_ATTR_RDONLY, propagation) {
+        log::warn!("make clone of {} ro: {e:#?}", source.display());
+    }
+
+    move_mount(
+        clone.as_fd(),
+        "",
+        CWD,
+        target,
+        MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+    )
+    .with_context(|| format!("attach {} -> {}", source.display(), target.display()))?;
+
+    Ok(())
+}
+
 fn merge_nodes(high: &mut Node, low: Node) {
     if high.module_path.is_none() {
         high.module_path = low.module_path;
@@ -151,7 +231,7 @@ fn process_module(
     Ok((root, system))
 }
 
-fn collect_module_files(
+pub(super) fn collect_module_files(
     module_paths: &[PathBuf],
     extra_partitions: &[String],
     exclusions: &HashMap<PathBuf, HashSet<String>>,
@@ -225,6 +305,25 @@ where
     Ok(())
 }
 
+fn copy_file<S>(src: S, dst: S) -> Result<()>
+where
+    S: AsRef<Path>,
+{
+    let metadata = src.as_ref().metadata()?;
+
+    fs::copy(src.as_ref(), dst.as_ref())?;
+
+    chown(
+        dst.as_ref(),
+        Some(Uid::from_raw(metadata.uid())),
+        Some(Gid::from_raw(metadata.gid())),
+    )?;
+
+    lsetfilecon(dst.as_ref(), lgetfilecon(src.as_ref())?.as_str())?;
+
+    Ok(())
+}
+
 fn mount_mirror<P>(path: P, work_dir_path: P, entry: &DirEntry) -> Result<()>
 where
     P: AsRef<Path>,
@@ -264,21 +363,71 @@ where
     Ok(())
 }
 
-struct MagicMount {
+pub(super) fn needs_tmpfs(node: &mut Node, path: &Path, attrs: &AttrIndex) -> bool {
+    for (name, child) in &mut node.children {
+        let real_path = path.join(name);
+
+        let need = match child.file_type {
+            NodeFileType::Symlink => true,
+            NodeFileType::Whiteout => real_path.exists(),
+            NodeFileType::RegularFile
+                if child
+                    .module_path
+                    .as_deref()
+                    .is_some_and(|p| attrs.resolve(p).is_some()) =>
+            {
+                true
+            }
+            _ => {
+                if let Ok(metadata) = real_path.symlink_metadata() {
+                    let file_type = NodeFileType::from(metadata.file_type());
+
+                    file_type != child.file_type || file_type == NodeFileType::Symlink
+                } else {
+                    true
+                }
+            }
+        };
+
+        if need {
+            if child.module_path.is_none() {
+                log::error!("cannot create tmpfs on {}, ignore: {name}", path.display());
+
+                child.skip = true;
+
+                continue;
+            }
+
+            return true;
+        }
+    }
+
+    false
+}
+
+struct MagicMount<'a> {
     node: Node,
     path: PathBuf,
     work_dir_path: PathBuf,
     has_tmpfs: bool,
+    attrs: &'a AttrIndex,
+    propagation: MountPropagationFlags,
+    detached: bool,
     #[cfg(any(target_os = "linux", target_os = "android"))]
     umount: bool,
 }
 
-impl MagicMount {
+impl<'a> MagicMount<'a> {
+    #[allow(clippy::too_many_arguments)]
+
     fn new<P>(
         node: &Node,
         path: P,
         work_dir_path: P,
         has_tmpfs: bool,
+        attrs: &'a AttrIndex,
+        propagation: MountPropagationFlags,
+        detached: bool,
         #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
     ) -> Self
     where
@@ -289,47 +438,23 @@ impl MagicMount {
             path: path.as_ref().join(node.name.clone()),
             work_dir_path: work_dir_path.as_ref().join(node.name.clone()),
             has_tmpfs,
+            attrs,
+            propagation,
+            detached,
             #[cfg(any(target_os = "linux", target_os = "android"))]
             umount,
         }
     }
 
-    fn check_tmpfs(&mut self) {
-        for it in &mut self.node.children {
-            let (name, node) = it;
-
-            let real_path = self.path.join(name);
-
-            let need = match node.file_type {
-                NodeFileType::Symlink => true,
-                NodeFileType::Whiteout => real_path.exists(),
-                _ => {
-                    if let Ok(metadata) = real_path.symlink_metadata() {
-                        let file_type = NodeFileType::from(metadata.file_type());
-
-                        file_type != node.file_type || file_type == NodeFileType::Symlink
-                    } else {
-                        true
-                    }
-                }
-            };
-
-            if need {
-                if node.module_path.is_none() {
-                    log::error!(
-                        "cannot create tmpfs on {}, ignore: {name}",
-                        self.path.display()
-                    );
-
-                    node.skip = true;
+    fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = path;
 
-                    continue;
-                }
-
-                self.has_tmpfs = true;
+        self
+    }
 
-                break;
-            }
+    fn check_tmpfs(&mut self) {
+        if needs_tmpfs(&mut self.node, &self.path, self.attrs) {
+            self.has_tmpfs = true;
         }
     }
 
@@ -362,6 +487,38 @@ impl MagicMount {
                 self.work_dir_path.display()
             );
 
+            if self.has_tmpfs && self.attrs.resolve(module_path).is_some() {
+                copy_file(module_path, &self.work_dir_path).with_context(|| {
+                    format!(
+                        "copy module file {} -> {}",
+                        module_path.display(),
+                        self.work_dir_path.display(),
+                    )
+                })?;
+
+                self.attrs.apply(module_path, &self.work_dir_path);
+
+                return Ok(());
+            }
+
+            if self.detached && !self.has_tmpfs {
+                attach_clone(
+                    module_path,
+                    target_path,
+                    false,
+                    MountPropagationFlags::empty(),
+                )
+                .with_context(|| {
+                    format!(
+                        "mount module file {} -> {}",
+                        module_path.display(),
+                        self.work_dir_path.display(),
+                    )
+                })?;
+
+                return Ok(());
+            }
+
             mount_bind(module_path, target_path).with_context(|| {
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 if self.umount {
@@ -425,6 +582,10 @@ impl MagicMount {
             )?;
 
             lsetfilecon(&self.work_dir_path, lgetfilecon(path)?.as_str())?;
+
+            if let Some(module_path) = &self.node.module_path {
+                self.attrs.apply(module_path, &self.work_dir_path);
+            }
         }
 
         if create_tmpfs {
@@ -460,6 +621,9 @@ impl MagicMount {
                             &self.path,
                             &self.work_dir_path,
                             has_tmpfs,
+                            self.attrs,
+                            self.propagation,
+                            self.detached,
                             #[cfg(any(target_os = "linux", target_os = "android"))]
                             self.umount,
                         )
@@ -504,6 +668,9 @@ impl MagicMount {
                 &self.path,
                 &self.work_dir_path,
                 has_tmpfs,
+                self.attrs,
+                self.propagation,
+                self.detached,
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 self.umount,
             )
@@ -518,7 +685,16 @@ impl MagicMount {
             }
         }
 
-        if create_tmpfs {
+        if create_tmpfs && self.detached {
+            log::debug!(
+                "attaching tmpfs {} -> {}",
+                self.work_dir_path.display(),
+                self.path.display()
+            );
+
+            attach_clone(&self.work_dir_path, &self.path, true, self.propagation)
+                .context("attach self")?;
+        } else if create_tmpfs {
             log::debug!(
                 "moving tmpfs {} -> {}",
                 self.work_dir_path.display(),
@@ -543,8 +719,12 @@ impl MagicMount {
                     )
                 })?;
 
-            if let Err(e) = mount_change(&self.path, MountPropagationFlags::PRIVATE) {
-                log::warn!("make dir {} private: {e:#?}", self.path.display());
+            if let Err(e) = mount_change(&self.path, self.propagation) {
+                log::warn!(
+                    "set propagation of dir {} to {:?}: {e:#?}",
+                    self.path.display(),
+                    self.propagation
+                );
             }
 
             #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -572,6 +752,8 @@ impl MagicMount {
                 )
             })?;
 
+            self.attrs.apply(module_path, &self.work_dir_path);
+
             Ok(())
         } else {
             bail!("cannot mount root symlink {}!", self.path.display());
@@ -579,12 +761,178 @@ impl MagicMount {
     }
 }
 
+pub(super) fn can_attach_detached(node: &Node, target: &Path, attrs: &AttrIndex) -> bool {
+    let caps = capabilities::get();
+
+    caps.new_mount_api
+        && caps.open_tree
+        && caps.detached_trees
+        && node.file_type == NodeFileType::Directory
+        && !node.replace
+        && !node.skip
+        && target.is_dir()
+        && !target.is_symlink()
+        && !needs_tmpfs(&mut node.clone(), target, attrs)
+}
+
+fn build_detached(
+    node: &Node,
+    target: &Path,
+    work_dir: &Path,
+    attrs: &AttrIndex,
+    propagation: MountPropagationFlags,
+) -> Result<()> {
+    let tree = open_tree(
+        CWD,
+        target,
+        OpenTreeFlags::OPEN_TREE_CLONE
+            | OpenTreeFlags::OPEN_TREE_CLOEXEC
+            | OpenTreeFlags::AT_RECURSIVE,
+    )
+    .with_context(|| format!("clone tree of {}", target.display()))?;
+
+    MagicMount::new(
+        node,
+        Path::new("/"),
+        work_dir,
+        false,
+        attrs,
+        propagation,
+        true,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        false,
+    )
+    .with_path(PathBuf::from(format!("/proc/self/fd/{}", tree.as_raw_fd())))
+    .do_magic_mount()?;
+
+    move_mount(
+        tree.as_fd(),
+        "",
+        CWD,
+        target,
+        MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+    )
+    .with_context(|| format!("attach magic tree to {}", target.display()))?;
+
+    Ok(())
+}
+
+fn mount_detached(
+    node: &Node,
+    target: &Path,
+    tmp_dir: &Path,
+    attrs: &AttrIndex,
+    propagation: MountPropagationFlags,
+    #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
+) -> Result<()> {
+    let work_dir = tmp_dir.join(".detached").join(&node.name);
+
+    create_dir_all(&work_dir)?;
+
+    mount_bind(&work_dir, &work_dir).context("bind detached work dir")?;
+
+    let result = build_detached(node, target, &work_dir, attrs, propagation);
+
+    if let Err(e) = unmount(&work_dir, UnmountFlags::DETACH) {
+        log::warn!("unmount detached work dir {}: {e}", work_dir.display());
+    }
+
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&work_dir);
+
+        return Err(e);
+    }
+
+    log::debug!("attached magic tree {} atomically", target.display());
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if umount {
+        let _ = send_unmountable(target);
+    }
+
+    Ok(())
+}
+
+fn mount_targets(
+    root: Node,
+    tmp_dir: &Path,
+    attrs: &AttrIndex,
+    modes: &HashMap<String, PropagationMode>,
+    #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
+) -> Result<()> {
+    let mut root_mount = MagicMount::new(
+        &root,
+        Path::new("/"),
+        tmp_dir,
+        false,
+        attrs,
+        propagation::tmpfs_flags(PropagationMode::Inherit),
+        false,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        umount,
+    );
+
+    root_mount.check_tmpfs();
+
+    let mut children: Vec<Node> = root_mount.node.children.into_values().collect();
+
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for node in children {
+        if node.skip {
+            continue;
+        }
+
+        let target = Path::new("/").join(&node.name);
+
+        let flags = propagation::tmpfs_flags(modes.get(&node.name).copied().unwrap_or_default());
+
+        if can_attach_detached(&node, &target, attrs) {
+            match mount_detached(
+                &node,
+                &target,
+                tmp_dir,
+                attrs,
+                flags,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                umount,
+            ) {
+                Ok(_) => continue,
+                Err(e) => log::warn!(
+                    "detached magic mount for {} failed: {:#}, falling back to in-place",
+                    target.display(),
+                    e
+                ),
+            }
+        }
+
+        if let Err(e) = MagicMount::new(
+            &node,
+            Path::new("/"),
+            tmp_dir,
+            false,
+            attrs,
+            flags,
+            false,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            umount,
+        )
+        .do_magic_mount()
+        {
+            log::error!("mount child {} failed: {e:#?}", target.display());
+        }
+    }
+
+    Ok(())
+}
+
 pub fn mount_partitions(
     tmp_path: &Path,
     module_paths: &[PathBuf],
     mount_source: &str,
     extra_partitions: &[String],
     exclusions: HashMap<PathBuf, HashSet<String>>,
+    propagation: &HashMap<String, PropagationMode>,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _disable_umount: bool,
 ) -> Result<()> {
@@ -612,17 +960,16 @@ pub fn mount_partitions(
 
         mount_change(&tmp_dir, MountPropagationFlags::PRIVATE).context("make tmp private")?;
 
-        let result = {
-            MagicMount::new(
-                &root,
-                Path::new("/"),
-                tmp_dir.as_path(),
-                false,
-                #[cfg(any(target_os = "linux", target_os = "android"))]
-                !disable_umount,
-            )
-            .do_magic_mount()
-        };
+        let attrs = AttrIndex::load(module_paths);
+
+        let result = mount_targets(
+            root,
+            tmp_dir.as_path(),
+            &attrs,
+            propagation,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            !disable_umount,
+        );
 
         if let Err(e) = unmount(&tmp_dir, UnmountFlags::DETACH) {
             log::error!("failed to unmount tmp {e}");
@@ -0,0 +1,293 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, read_link},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    core::attrs::AttrIndex,
+    mount::{
+        magic::{can_attach_detached, collect_module_files, needs_tmpfs},
+        node::{Node, NodeFileType},
+    },
+};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+
+pub enum MagicOperation {
+    Target {
+        path: PathBuf,
+        detached: bool,
+    },
+    TmpfsSkeleton {
+        path: PathBuf,
+        replace: bool,
+    },
+    TmpfsAttach {
+        path: PathBuf,
+    },
+    MirrorBind {
+        path: PathBuf,
+        kind: String,
+    },
+    FileBind {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    FileCopy {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    SymlinkClone {
+        source: PathBuf,
+        target: PathBuf,
+        link: Option<PathBuf>,
+    },
+    Whiteout {
+        path: PathBuf,
+    },
+    Skipped {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl fmt::Display for MagicOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Target { path, detached } => write!(
+                f,
+                "TARGET   {} ({})",
+                path.display(),
+                if *detached { "detached" } else { "in-place" }
+            ),
+            Self::TmpfsSkeleton { path, replace } => write!(
+                f,
+                "TMPFS    {}{}",
+                path.display(),
+                if *replace { " [REPLACE]" } else { "" }
+            ),
+            Self::TmpfsAttach { path } => write!(f, "ATTACH   {}", path.display()),
+            Self::MirrorBind { path, kind } => {
+                write!(f, "MIRROR   {} [{}]", path.display(), kind)
+            }
+            Self::FileBind { source, target } => {
+                write!(f, "BIND     {} <- {}", target.display(), source.display())
+            }
+            Self::FileCopy { source, target } => {
+                write!(f, "COPY     {} <- {}", target.display(), source.display())
+            }
+            Self::SymlinkClone {
+                source,
+                target,
+                link,
+            } => match link {
+                Some(link) => write!(
+                    f,
+                    "SYMLINK  {} -> {} (from {})",
+                    target.display(),
+                    link.display(),
+                    source.display()
+                ),
+                None => write!(
+                    f,
+                    "SYMLINK  {} (from {}, unreadable)",
+                    target.display(),
+                    source.display()
+                ),
+            },
+            Self::Whiteout { path } => write!(f, "WHITEOUT {}", path.display()),
+            Self::Skipped { path, reason } => {
+                write!(f, "SKIP     {} ({})", path.display(), reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+
+pub struct MagicPreview {
+    pub operations: Vec<MagicOperation>,
+    #[serde(skip)]
+    attrs: AttrIndex,
+}
+
+impl MagicPreview {
+    pub fn count(&self, predicate: fn(&MagicOperation) -> bool) -> usize {
+        self.operations.iter().filter(|op| predicate(op)).count()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} targets, {} tmpfs dirs, {} mirrors, {} file binds, {} file copies, {} symlinks, {} whiteouts, {} skipped",
+            self.count(|op| matches!(op, MagicOperation::Target { .. })),
+            self.count(|op| matches!(op, MagicOperation::TmpfsSkeleton { .. })),
+            self.count(|op| matches!(op, MagicOperation::MirrorBind { .. })),
+            self.count(|op| matches!(op, MagicOperation::FileBind { .. })),
+            self.count(|op| matches!(op, MagicOperation::FileCopy { .. })),
+            self.count(|op| matches!(op, MagicOperation::SymlinkClone { .. })),
+            self.count(|op| matches!(op, MagicOperation::Whiteout { .. })),
+            self.count(|op| matches!(op, MagicOperation::Skipped { .. })),
+        )
+    }
+
+    fn walk(&mut self, node: &Node, parent: &Path, has_tmpfs: bool) {
+        let path = parent.join(&node.name);
+
+        match node.file_type {
+            NodeFileType::RegularFile => match &node.module_path {
+                Some(source) if has_tmpfs && self.attrs.resolve(source).is_some() => {
+                    self.operations.push(MagicOperation::FileCopy {
+                        source: source.clone(),
+                        target: path,
+                    })
+                }
+                Some(source) => self.operations.push(MagicOperation::FileBind {
+                    source: source.clone(),
+                    target: path,
+                }),
+                None => self.operations.push(MagicOperation::Skipped {
+                    path,
+                    reason: "root file without module source".to_string(),
+                }),
+            },
+            NodeFileType::Symlink => match &node.module_path {
+                Some(source) => self.operations.push(MagicOperation::SymlinkClone {
+                    source: source.clone(),
+                    target: path,
+                    link: read_link(source).ok(),
+                }),
+                None => self.operations.push(MagicOperation::Skipped {
+                    path,
+                    reason: "root symlink without module source".to_string(),
+                }),
+            },
+            NodeFileType::Whiteout => self.operations.push(MagicOperation::Whiteout { path }),
+            NodeFileType::Directory => self.walk_directory(node.clone(), path, has_tmpfs),
+        }
+    }
+
+    fn walk_directory(&mut self, mut node: Node, path: PathBuf, has_tmpfs: bool) {
+        let mut create_tmpfs = !has_tmpfs && node.replace && node.module_path.is_some();
+
+        if !has_tmpfs && !create_tmpfs {
+            create_tmpfs = needs_tmpfs(&mut node, &path, &self.attrs);
+        }
+
+        let has_tmpfs = has_tmpfs || create_tmpfs;
+
+        if has_tmpfs {
+            self.operations.push(MagicOperation::TmpfsSkeleton {
+                path: path.clone(),
+                replace: node.replace,
+            });
+        }
+
+        if path.exists()
+            && !node.replace
+            && let Ok(entries) = fs::read_dir(&path)
+        {
+            let mut entries: Vec<_> = entries.flatten().collect();
+
+            entries.sort_by_key(|e| e.file_name());
+
+            for entry in entries {
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                if let Some(child) = node.children.remove(&name) {
+                    self.walk_child(&child, &path, has_tmpfs);
+                } else if has_tmpfs {
+                    let kind = match entry.file_type() {
+                        Ok(t) if t.is_dir() => "dir",
+                        Ok(t) if t.is_symlink() => "symlink",
+                        Ok(t) if t.is_file() => "file",
+                        _ => "other",
+                    };
+
+                    self.operations.push(MagicOperation::MirrorBind {
+                        path: entry.path(),
+                        kind: kind.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut children: Vec<&Node> = node.children.values().collect();
+
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for child in children {
+            self.walk_child(child, &path, has_tmpfs);
+        }
+
+        if create_tmpfs {
+            self.operations.push(MagicOperation::TmpfsAttach { path });
+        }
+    }
+
+    fn walk_child(&mut self, child: &Node, parent: &Path, has_tmpfs: bool) {
+        if child.skip {
+            self.operations.push(MagicOperation::Skipped {
+                path: parent.join(&child.name),
+                reason: "no module source to build tmpfs from".to_string(),
+            });
+
+            return;
+        }
+
+        self.walk(child, parent, has_tmpfs);
+    }
+}
+
+pub fn preview_partitions(
+    module_paths: &[PathBuf],
+    extra_partitions: &[String],
+    exclusions: &HashMap<PathBuf, HashSet<String>>,
+) -> Result<MagicPreview> {
+    let mut preview = MagicPreview {
+        attrs: AttrIndex::load(module_paths),
+        ..Default::default()
+    };
+
+    let Some(mut root) = collect_module_files(module_paths, extra_partitions, exclusions)? else {
+        return Ok(preview);
+    };
+
+    let root_path = Path::new("/");
+
+    needs_tmpfs(&mut root, root_path, &preview.attrs);
+
+    let mut children: Vec<Node> = root.children.into_values().collect();
+
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for node in children {
+        let target = root_path.join(&node.name);
+
+        if node.skip {
+            preview.operations.push(MagicOperation::Skipped {
+                path: target,
+                reason: "no module source to build tmpfs from".to_string(),
+            });
+
+            continue;
+        }
+
+        preview.operations.push(MagicOperation::Target {
+            path: target.clone(),
+            detached: can_attach_detached(&node, &target, &preview.attrs),
+        });
+
+        preview.walk(&node, root_path, false);
+    }
+
+    Ok(preview)
+}
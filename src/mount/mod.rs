@@ -1,6 +1,10 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod loopdev;
 pub mod magic;
+pub mod magic_preview;
+pub mod mountinfo;
 pub mod node;
 pub mod overlay;
+pub mod propagation;
@@ -0,0 +1,289 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+
+pub struct Propagation {
+    pub shared: Option<u32>,
+    pub master: Option<u32>,
+    pub propagate_from: Option<u32>,
+    pub unbindable: bool,
+}
+
+impl Propagation {
+    fn parse(fields: &[&str]) -> Self {
+        let mut propagation = Self::default();
+
+        for field in fields {
+            match field.split_once(':') {
+                Some(("shared", id)) => propagation.shared = id.parse().ok(),
+                Some(("master", id)) => propagation.master = id.parse().ok(),
+                Some(("propagate_from", id)) => propagation.propagate_from = id.parse().ok(),
+                _ if *field == "unbindable" => propagation.unbindable = true,
+                _ => {}
+            }
+        }
+
+        propagation
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+
+pub struct MountEntry {
+    pub mount_id: u32,
+    pub parent_id: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub mount_options: Vec<String>,
+    pub propagation: Propagation,
+    pub fs_type: String,
+    pub source: String,
+    pub super_options: Vec<String>,
+}
+
+impl MountEntry {
+    fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split(' ').collect();
+
+        let separator = fields
+            .iter()
+            .position(|f| *f == "-")
+            .context("missing optional field separator")?;
+
+        if separator < 6 || fields.len() < separator + 3 {
+            bail!("truncated mountinfo line");
+        }
+
+        let (major, minor) = fields[2]
+            .split_once(':')
+            .context("malformed device number")?;
+
+        Ok(Self {
+            mount_id: fields[0].parse().context("malformed mount id")?,
+            parent_id: fields[1].parse().context("malformed parent id")?,
+            major: major.parse().context("malformed major")?,
+            minor: minor.parse().context("malformed minor")?,
+            root: PathBuf::from(unescape(fields[3])),
+            mount_point: PathBuf::from(unescape(fields[4])),
+            mount_options: fields[5].split(',').map(String::from).collect(),
+            propagation: Propagation::parse(&fields[6..separator]),
+            fs_type: unescape(fields[separator + 1]),
+            source: unescape(fields[separator + 2]),
+            super_options: fields
+                .get(separator + 3)
+                .map(|o| o.split(',').map(String::from).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+
+pub struct MountDiff {
+    pub added: Vec<MountEntry>,
+    pub removed: Vec<MountEntry>,
+}
+
+impl MountDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+
+pub struct MountTable {
+    entries: Vec<MountEntry>,
+}
+
+impl MountTable {
+    pub fn read_self() -> Result<Self> {
+        Self::from_path(Path::new("/proc/self/mountinfo"))
+    }
+
+    pub fn read_pid(pid: u32) -> Result<Self> {
+        Self::from_path(&PathBuf::from(format!("/proc/{}/mountinfo", pid)))
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let entries = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                MountEntry::parse(line)
+                    .with_context(|| format!("Failed to parse mountinfo line: {}", line))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { entries })
+    }
+
+    pub fn mounts_at<P: AsRef<Path>>(&self, path: P) -> impl Iterator<Item = &MountEntry> {
+        let path = normalize(path.as_ref());
+
+        self.entries.iter().filter(move |e| e.mount_point == path)
+    }
+
+    pub fn is_mount_point<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.mounts_at(path).next().is_some()
+    }
+
+    pub fn covering<P: AsRef<Path>>(&self, path: P) -> Option<&MountEntry> {
+        let path = normalize(path.as_ref());
+
+        self.entries
+            .iter()
+            .filter(|e| path.starts_with(&e.mount_point))
+            .fold(None, |best: Option<&MountEntry>, e| match best {
+                Some(b) if b.mount_point.as_os_str().len() > e.mount_point.as_os_str().len() => {
+                    Some(b)
+                }
+                _ => Some(e),
+            })
+    }
+
+    pub fn children_of(&self, mount_id: u32) -> impl Iterator<Item = &MountEntry> {
+        self.entries
+            .iter()
+            .filter(move |e| e.parent_id == mount_id && e.mount_id != mount_id)
+    }
+
+    pub fn mounts_under<P: AsRef<Path>>(&self, path: P) -> Vec<&MountEntry> {
+        let path = normalize(path.as_ref());
+
+        let Some(root) = self.covering(&path) else {
+            return Vec::new();
+        };
+
+        let mut seen = HashSet::new();
+
+        let mut mounts = Vec::new();
+
+        let mut pending = vec![root.mount_id];
+
+        while let Some(mount_id) = pending.pop() {
+            for child in self.children_of(mount_id) {
+                if child.mount_point == path || !child.mount_point.starts_with(&path) {
+                    continue;
+                }
+
+                pending.push(child.mount_id);
+
+                if seen.insert(child.mount_point.clone()) {
+                    mounts.push(child);
+                }
+            }
+        }
+
+        mounts.sort_by_key(|e| e.mount_point.components().count());
+
+        mounts
+    }
+
+    pub fn users_of<P: AsRef<Path>>(&self, path: P) -> Vec<&MountEntry> {
+        let path = normalize(path.as_ref());
+
+        let device = self.mounts_at(&path).last().map(|e| (e.major, e.minor));
+
+        self.entries
+            .iter()
+            .filter(|e| !e.mount_point.starts_with(&path))
+            .filter(|e| {
+                let layered = e.fs_type == "overlay"
+                    && e.super_options.iter().any(|opt| {
+                        opt.split_once('=').is_some_and(|(key, dirs)| {
+                            matches!(key, "lowerdir" | "lowerdir+" | "upperdir" | "workdir")
+                                && dirs
+                                    .split(':')
+                                    .any(|dir| Path::new(&unescape(dir)).starts_with(&path))
+                        })
+                    });
+
+                layered || device == Some((e.major, e.minor))
+            })
+            .collect()
+    }
+
+    pub fn diff(&self, newer: &MountTable) -> MountDiff {
+        let old_ids: HashSet<u32> = self.entries.iter().map(|e| e.mount_id).collect();
+
+        let new_ids: HashSet<u32> = newer.entries.iter().map(|e| e.mount_id).collect();
+
+        MountDiff {
+            added: newer
+                .entries
+                .iter()
+                .filter(|e| !old_ids.contains(&e.mount_id))
+                .cloned()
+                .collect(),
+            removed: self
+                .entries
+                .iter()
+                .filter(|e| !new_ids.contains(&e.mount_id))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let s = path.to_string_lossy();
+
+    let trimmed = s.trim_end_matches('/');
+
+    if trimmed.is_empty() {
+        PathBuf::from("/")
+    } else {
+        PathBuf::from(trimmed)
+    }
+}
+
+pub fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+
+    let mut out = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && bytes[i + 1..i + 4]
+                .iter()
+                .all(|b| (b'0'..=b'7').contains(b))
+        {
+            let value = bytes[i + 1..i + 4]
+                .iter()
+                .fold(0u32, |acc, b| acc * 8 + u32::from(b - b'0'));
+
+            out.push(value as u8);
+
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -6,21 +6,21 @@ use log::{info, warn};
 use std::{
     ffi::CString,
     fs,
-    io::{BufRead, BufReader},
     os::fd::{AsRawFd, OwnedFd},
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use rustix::{
-    fd::AsFd,
-    fs::{CWD, XattrFlags, getxattr, setxattr},
-    mount::*,
-};
+use rustix::{fd::AsFd, fs::CWD, mount::*};
 
-use crate::defs::{KSU_OVERLAY_SOURCE, RUN_DIR};
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use crate::try_umount::send_unmountable;
+use crate::{
+    core::capabilities,
+    defs::{HYBRID_MNT_DIR, KSU_OVERLAY_SOURCE, RUN_DIR},
+    mount::mountinfo::MountTable,
+    selinux,
+};
 
 const PAGE_LIMIT: usize = 4000;
 
@@ -56,17 +56,15 @@ fn umount_dir(src: impl AsRef<Path>) -> Result<()> {
 }
 
 fn get_overlay_features() -> String {
+    let overlay = &capabilities::get().overlay;
+
     let mut features = String::new();
 
-    if Path::new("/sys/module/overlay/parameters/redirect_dir").exists() {
+    if overlay.redirect_dir || overlay.metacopy {
         features.push_str(",redirect_dir=on");
     }
 
-    if Path::new("/sys/module/overlay/parameters/metacopy").exists() {
-        if !features.contains("redirect_dir") {
-            features.push_str(",redirect_dir=on");
-        }
-
+    if overlay.metacopy {
         features.push_str(",metacopy=on");
     }
 
@@ -74,98 +72,66 @@ fn get_overlay_features() -> String {
 }
 
 fn get_sub_mounts(parent: &str) -> Result<Vec<String>> {
-    let file = fs::File::open("/proc/mounts").context("Failed to open /proc/mounts")?;
-
-    let reader = BufReader::new(file);
+    let table = MountTable::read_self()?;
 
-    let mut sub_mounts = Vec::new();
+    let private_roots = [Path::new(HYBRID_MNT_DIR), Path::new(RUN_DIR)];
 
-    let parent_prefix = if parent.ends_with('/') {
-        parent.to_string()
-    } else {
-        format!("{}/", parent)
-    };
-
-    for line in reader.lines() {
-        let line = line?;
-
-        let parts: Vec<&str> = line.split_whitespace().collect();
-
-        if parts.len() < 2 {
-            continue;
-        }
-
-        let mount_point = parts[1];
-
-        if mount_point.starts_with(&parent_prefix)
-            && mount_point != parent
-            && !mount_point.contains("hybrid_mount")
-        {
-            sub_mounts.push(mount_point.to_string());
-        }
-    }
-
-    sub_mounts.sort_by_key(|a| a.len());
+    let sub_mounts = table
+        .mounts_under(parent)
+        .into_iter()
+        .filter(|m| !private_roots.iter().any(|r| m.mount_point.starts_with(r)))
+        .map(|m| m.mount_point.to_string_lossy().to_string())
+        .collect();
 
     Ok(sub_mounts)
 }
 
-fn clone_path_context(source: &Path, target: &Path) -> Result<()> {
-    let mut buf = vec![0u8; 256];
+fn align_overlay_contexts(target_root: &str, module_roots: &[String]) {
+    let target_path = Path::new(target_root);
 
-    let name = "security.selinux";
+    for module_root in module_roots {
+        let module_path = Path::new(module_root);
 
-    match getxattr(source, name, &mut buf) {
-        Ok(len) => {
-            let _ = setxattr(target, name, &buf[..len], XattrFlags::empty());
+        if !module_path.exists() {
+            continue;
         }
-        Err(rustix::io::Errno::RANGE) => {
-            let mut large_buf = vec![0u8; 1024];
 
-            if let Ok(len) = getxattr(source, name, &mut large_buf) {
-                let _ = setxattr(target, name, &large_buf[..len], XattrFlags::empty());
+        let report = selinux::relabel_tree(module_path, |path, file_type| {
+            if file_type.is_dir() || file_type.is_symlink() {
+                return None;
             }
-        }
-        _ => {}
-    }
 
-    Ok(())
-}
+            selinux::mirror_context(path, module_path, target_path, false)
+        });
 
-fn recursive_context_align(
-    target_base: &Path,
-    module_base: &Path,
-    current_module_path: &Path,
-) -> Result<()> {
-    if current_module_path.is_symlink() {
-        return Ok(());
+        report.log_summary(module_root);
     }
+}
 
-    if current_module_path.is_dir() {
-        for entry in fs::read_dir(current_module_path)? {
-            let entry = entry?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 
-            recursive_context_align(target_base, module_base, &entry.path())?;
-        }
-    } else if let Ok(relative) = current_module_path.strip_prefix(module_base) {
-        let target_path = target_base.join(relative);
+pub enum OverlayMethod {
+    LowerdirAppend,
+    Direct,
+    Staged,
+}
 
-        if target_path.exists() {
-            let _ = clone_path_context(&target_path, current_module_path);
+impl OverlayMethod {
+    pub fn select(lowerdir_len: usize) -> Self {
+        if capabilities::get().overlay.lowerdir_append {
+            Self::LowerdirAppend
+        } else if lowerdir_len >= PAGE_LIMIT {
+            Self::Staged
+        } else {
+            Self::Direct
         }
     }
 
-    Ok(())
-}
-
-fn align_overlay_contexts(target_root: &str, module_roots: &[String]) {
-    let target_path = Path::new(target_root);
-
-    for module_root in module_roots {
-        let module_path = Path::new(module_root);
-
-        if module_path.exists() {
-            let _ = recursive_context_align(target_path, module_path, module_path);
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::LowerdirAppend => "lowerdir+",
+            Self::Direct => "direct",
+            Self::Staged => "staged",
         }
     }
 }
@@ -176,17 +142,27 @@ pub fn mount_overlayfs(
     upperdir: Option<PathBuf>,
     workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
+    method: OverlayMethod,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
 ) -> Result<()> {
-    let lowerdir_config = lower_dirs
+    let layers = lower_dirs
         .iter()
-        .map(|s| s.as_ref())
+        .map(|s| s.as_str())
         .chain(std::iter::once(lowest))
-        .collect::<Vec<_>>()
-        .join(":");
+        .collect::<Vec<_>>();
+
+    let lowerdir_len = layers.iter().map(|l| l.len() + 1).sum::<usize>();
+
+    log::debug!(
+        "Overlay method for {}: {} ({} layers)",
+        dest.as_ref().display(),
+        method.as_str(),
+        layers.len()
+    );
 
     match do_mount_overlay(
-        &lowerdir_config,
+        &layers,
+        method,
         upperdir.clone(),
         workdir.clone(),
         dest.as_ref(),
@@ -195,13 +171,14 @@ pub fn mount_overlayfs(
     ) {
         Ok(_) => Ok(()),
         Err(e) => {
-            if lowerdir_config.len() >= PAGE_LIMIT {
+            if method == OverlayMethod::Staged || lowerdir_len >= PAGE_LIMIT {
                 if upperdir.is_some() || workdir.is_some() {
                     return Err(e);
                 }
 
                 info!(
-                    "Direct overlay mount failed (possibly due to length limits), switching to staged mount. Error: {}",
+                    "{} overlay mount failed (possibly due to length limits), switching to staged mount. Error: {}",
+                    method.as_str(),
                     e
                 );
 
@@ -283,15 +260,15 @@ fn mount_overlayfs_staged(
             stage_dir
         };
 
-        let lowerdir_str = batch
+        let layers = batch
             .iter()
             .map(|s| s.as_str())
             .chain(std::iter::once(current_base.as_str()))
-            .collect::<Vec<_>>()
-            .join(":");
+            .collect::<Vec<_>>();
 
         do_mount_overlay(
-            &lowerdir_str,
+            &layers,
+            OverlayMethod::Direct,
             None,
             None,
             &target_path,
@@ -312,7 +289,8 @@ fn mount_overlayfs_staged(
 }
 
 fn do_mount_overlay(
-    lowerdir_config: &str,
+    layers: &[&str],
+    method: OverlayMethod,
     upperdir: Option<PathBuf>,
     workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
@@ -326,14 +304,26 @@ fn do_mount_overlay(
         .filter(|wd| wd.exists())
         .map(|e| e.display().to_string());
 
+    let lowerdir_config = layers.join(":");
+
     let extra_features = get_overlay_features();
 
     let result = (|| {
+        if !capabilities::get().new_mount_api {
+            return Err(rustix::io::Errno::NOSYS);
+        }
+
         let fs = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)?;
 
         let fs = fs.as_fd();
 
-        fsconfig_set_string(fs, "lowerdir", lowerdir_config)?;
+        if method == OverlayMethod::LowerdirAppend {
+            for layer in layers {
+                fsconfig_set_string(fs, "lowerdir+", *layer)?;
+            }
+        } else {
+            fsconfig_set_string(fs, "lowerdir", &lowerdir_config)?;
+        }
 
         if let (Some(upperdir), Some(workdir)) = (&upperdir_s, &workdir_s) {
             fsconfig_set_string(fs, "upperdir", upperdir)?;
@@ -398,6 +388,7 @@ fn mount_overlay_child(
     relative: &str,
     module_roots: &[String],
     stock: StashedMount,
+    method: OverlayMethod,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
 ) -> Result<()> {
     let has_modification = module_roots.iter().any(|lower| {
@@ -464,6 +455,7 @@ fn mount_overlay_child(
         None,
         None,
         mount_point,
+        method,
         #[cfg(any(target_os = "linux", target_os = "android"))]
         disable_umount,
     ) {
@@ -508,9 +500,18 @@ pub fn mount_overlay(
     module_roots: &[String],
     workdir: Option<PathBuf>,
     upperdir: Option<PathBuf>,
+    method: OverlayMethod,
+    align_contexts: bool,
     #[cfg(any(target_os = "linux", target_os = "android"))] disable_umount: bool,
 ) -> Result<()> {
-    align_overlay_contexts(target_root, module_roots);
+    if align_contexts {
+        align_overlay_contexts(target_root, module_roots);
+    } else {
+        log::debug!(
+            "Layers of {} are bound from the module source, skipping context alignment",
+            target_root
+        );
+    }
 
     let root_file = fs::File::open(target_root)
         .with_context(|| format!("failed to open target root {}", target_root))?;
@@ -544,13 +545,19 @@ pub fn mount_overlay(
 
         let relative_clean = relative.trim_start_matches('/');
 
-        match open_tree(
-            root_file.as_fd(),
-            relative_clean,
-            OpenTreeFlags::OPEN_TREE_CLOEXEC
-                | OpenTreeFlags::OPEN_TREE_CLONE
-                | OpenTreeFlags::AT_RECURSIVE,
-        ) {
+        let stashed = if capabilities::get().open_tree {
+            open_tree(
+                root_file.as_fd(),
+                relative_clean,
+                OpenTreeFlags::OPEN_TREE_CLOEXEC
+                    | OpenTreeFlags::OPEN_TREE_CLONE
+                    | OpenTreeFlags::AT_RECURSIVE,
+            )
+        } else {
+            Err(rustix::io::Errno::NOSYS)
+        };
+
+        match stashed {
             Ok(fd) => {
                 stashed_mounts.push((mount_point.clone(), relative, StashedMount::Modern(fd)))
             }
@@ -599,6 +606,7 @@ pub fn mount_overlay(
         upperdir,
         workdir,
         target_root,
+        method,
         #[cfg(any(target_os = "linux", target_os = "android"))]
         disable_umount,
     )
@@ -610,6 +618,7 @@ pub fn mount_overlay(
             &relative,
             module_roots,
             stock,
+            method,
             #[cfg(any(target_os = "linux", target_os = "android"))]
             disable_umount,
         ) {
@@ -0,0 +1,164 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rustix::mount::{
+    MountPropagationFlags, UnmountFlags, mount_bind_recursive, mount_change, unmount,
+};
+
+use crate::{conf::config::PropagationMode, mount::mountinfo::MountTable};
+
+const ZYGOTE_NAMES: [&str; 2] = ["zygote64", "zygote"];
+
+#[derive(Debug)]
+
+pub struct PropagationCheck {
+    pub partition: String,
+    pub target: PathBuf,
+    pub mode: PropagationMode,
+    pub local_fs_type: String,
+    pub visible: bool,
+}
+
+impl PropagationCheck {
+    pub fn expect_visible(&self) -> bool {
+        self.mode != PropagationMode::Private
+    }
+
+    pub fn matches_expectation(&self) -> bool {
+        self.visible == self.expect_visible()
+    }
+}
+
+fn flags(mode: PropagationMode) -> Option<MountPropagationFlags> {
+    match mode {
+        PropagationMode::Inherit => None,
+        PropagationMode::Shared => Some(MountPropagationFlags::SHARED),
+        PropagationMode::Slave => Some(MountPropagationFlags::DOWNSTREAM),
+        PropagationMode::Private => Some(MountPropagationFlags::PRIVATE),
+    }
+}
+
+pub fn tmpfs_flags(mode: PropagationMode) -> MountPropagationFlags {
+    flags(mode).unwrap_or(MountPropagationFlags::PRIVATE)
+}
+
+pub fn apply(target: &Path, mode: PropagationMode) -> Result<()> {
+    let Some(flags) = flags(mode) else {
+        return Ok(());
+    };
+
+    mount_change(target, flags)
+        .with_context(|| format!("Failed to make {} {}", target.display(), mode.as_str()))?;
+
+    log::debug!(
+        "Propagation of {} set to {}",
+        target.display(),
+        mode.as_str()
+    );
+
+    Ok(())
+}
+
+pub fn prepare(target: &Path, mode: PropagationMode, table: Option<&MountTable>) -> Result<bool> {
+    if mode == PropagationMode::Inherit {
+        return Ok(false);
+    }
+
+    if !target.is_dir() {
+        bail!("{} is not a directory", target.display());
+    }
+
+    let bound = !table.is_some_and(|t| t.is_mount_point(target));
+
+    if bound {
+        mount_bind_recursive(target, target)
+            .with_context(|| format!("Failed to bind {} onto itself", target.display()))?;
+
+        log::debug!("Bound {} onto itself to hold propagation", target.display());
+    }
+
+    if let Err(e) = apply(target, mode) {
+        if bound {
+            let _ = release(target);
+        }
+
+        return Err(e);
+    }
+
+    Ok(bound)
+}
+
+pub fn release(target: &Path) -> Result<()> {
+    unmount(target, UnmountFlags::DETACH)
+        .with_context(|| format!("Failed to release self-bind of {}", target.display()))?;
+
+    log::debug!("Released self-bind of {}", target.display());
+
+    Ok(())
+}
+
+pub fn find_zygote_pid() -> Option<u32> {
+    let mut found: Vec<(usize, u32)> = fs::read_dir("/proc")
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+
+            let argv0 = cmdline.split(|b| *b == 0).next()?;
+
+            let argv0 = std::str::from_utf8(argv0).ok()?;
+
+            let rank = ZYGOTE_NAMES.iter().position(|name| *name == argv0)?;
+
+            Some((rank, pid))
+        })
+        .collect();
+
+    found.sort();
+
+    found.first().map(|(_, pid)| *pid)
+}
+
+pub fn shares_namespace(pid: u32) -> bool {
+    let ours = fs::read_link("/proc/self/ns/mnt");
+
+    let theirs = fs::read_link(format!("/proc/{}/ns/mnt", pid));
+
+    matches!((ours, theirs), (Ok(a), Ok(b)) if a == b)
+}
+
+pub fn verify(
+    local: &MountTable,
+    remote: &MountTable,
+    targets: &[(String, PathBuf, PropagationMode)],
+) -> Vec<PropagationCheck> {
+    targets
+        .iter()
+        .filter_map(|(partition, target, mode)| {
+            let ours = local.mounts_at(target).last()?;
+
+            let visible = remote.mounts_at(target).any(|theirs| {
+                theirs.fs_type == ours.fs_type
+                    && theirs.major == ours.major
+                    && theirs.minor == ours.minor
+                    && theirs.root == ours.root
+            });
+
+            Some(PropagationCheck {
+                partition: partition.clone(),
+                target: target.clone(),
+                mode: *mode,
+                local_fs_type: ours.fs_type.clone(),
+                visible,
+            })
+        })
+        .collect()
+}
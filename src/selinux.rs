@@ -0,0 +1,188 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::FileType,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use extattr::{Flags as XattrFlags, lgetxattr, lsetxattr};
+
+pub const SELINUX_XATTR: &str = "security.selinux";
+
+pub const DEFAULT_CONTEXT: &str = "u:object_r:system_file:s0";
+
+#[derive(Debug)]
+
+pub struct LabelFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+
+pub struct LabelReport {
+    pub scanned: usize,
+    pub changed: usize,
+    pub failures: Vec<LabelFailure>,
+}
+
+impl LabelReport {
+    pub fn merge(&mut self, other: LabelReport) {
+        self.scanned += other.scanned;
+
+        self.changed += other.changed;
+
+        self.failures.extend(other.failures);
+    }
+
+    pub fn log_summary(&self, scope: &str) {
+        if self.failures.is_empty() {
+            log::debug!(
+                "Relabel {}: {} scanned, {} changed",
+                scope,
+                self.scanned,
+                self.changed
+            );
+
+            return;
+        }
+
+        log::warn!(
+            "Relabel {}: {} scanned, {} changed, {} failed",
+            scope,
+            self.scanned,
+            self.changed,
+            self.failures.len()
+        );
+
+        for failure in &self.failures {
+            log::debug!("   {} -> {}", failure.path.display(), failure.error);
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+pub fn lgetfilecon<P: AsRef<Path>>(path: P) -> Result<String> {
+    let con = lgetxattr(&path, SELINUX_XATTR).with_context(|| {
+        format!(
+            "Failed to get SELinux context for {}",
+            path.as_ref().display()
+        )
+    })?;
+
+    let con = String::from_utf8_lossy(&con);
+
+    Ok(con.trim_end_matches('\0').to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+pub fn lgetfilecon<P: AsRef<Path>>(_path: P) -> Result<String> {
+    Ok(DEFAULT_CONTEXT.to_string())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+pub fn setfilecon_strict<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
+    lsetxattr(&path, SELINUX_XATTR, con, XattrFlags::empty())
+        .map_err(std::io::Error::from)
+        .with_context(|| {
+            format!(
+                "Failed to set SELinux context {} on {}",
+                con,
+                path.as_ref().display()
+            )
+        })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+pub fn setfilecon_strict<P: AsRef<Path>>(_path: P, _con: &str) -> Result<()> {
+    Ok(())
+}
+
+pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
+    if let Err(e) = setfilecon_strict(&path, con) {
+        log::debug!("lsetfilecon: {:#}", e);
+    }
+
+    Ok(())
+}
+
+pub fn apply_context(path: &Path, con: &str) -> Result<bool> {
+    if lgetfilecon(path).is_ok_and(|current| current == con) {
+        return Ok(false);
+    }
+
+    setfilecon_strict(path, con)?;
+
+    Ok(true)
+}
+
+pub fn mirror_context(
+    path: &Path,
+    base: &Path,
+    reference_root: &Path,
+    inherit_parent: bool,
+) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+
+    let counterpart = reference_root.join(relative);
+
+    if counterpart.exists() {
+        return lgetfilecon(&counterpart).ok();
+    }
+
+    if inherit_parent
+        && let Some(parent) = counterpart.parent()
+        && parent.exists()
+    {
+        return lgetfilecon(parent).ok();
+    }
+
+    None
+}
+
+pub fn relabel_tree<F>(root: &Path, mut resolve: F) -> LabelReport
+where
+    F: FnMut(&Path, &FileType) -> Option<String>,
+{
+    let mut report = LabelReport::default();
+
+    for entry in WalkDir::new(root).follow_links(false) {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                report.failures.push(LabelFailure {
+                    path: e.path().unwrap_or(root).to_path_buf(),
+                    error: e.to_string(),
+                });
+
+                continue;
+            }
+        };
+
+        report.scanned += 1;
+
+        let Some(con) = resolve(entry.path(), &entry.file_type()) else {
+            continue;
+        };
+
+        match apply_context(entry.path(), &con) {
+            Ok(true) => report.changed += 1,
+            Ok(false) => {}
+            Err(e) => report.failures.push(LabelFailure {
+                path: entry.path().to_path_buf(),
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+
+    report
+}
@@ -63,6 +63,10 @@ fn grab_fd() -> i32 {
     fd
 }
 
+pub fn is_driver_available() -> bool {
+    *DRIVER_FD.get_or_init(grab_fd) >= 0
+}
+
 pub fn send_unmountable<P>(target: P) -> Result<()>
 where
     P: AsRef<Path>,
@@ -87,6 +91,23 @@ where
 
     set.insert(path_str.clone());
 
+    add_try_umount(path_str)
+}
+
+pub fn send_stacked_unmountable<P>(target: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path_str = target.as_ref().to_string_lossy().to_string();
+
+    if path_str.is_empty() {
+        return Ok(());
+    }
+
+    add_try_umount(path_str)
+}
+
+fn add_try_umount(path_str: String) -> Result<()> {
     let path = CString::new(path_str)?;
 
     let cmd = KsuAddTryUmount {
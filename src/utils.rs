@@ -2,11 +2,12 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::{BTreeMap, HashMap},
     ffi::CString,
     fmt as std_fmt,
     fs::{self, File, create_dir_all, remove_dir_all, remove_file, write},
-    io::Write,
-    os::unix::fs::{PermissionsExt, symlink},
+    io::{Read, Write},
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt, symlink},
     path::{Path, PathBuf},
     process::{Command, Stdio},
     sync::OnceLock,
@@ -14,13 +15,16 @@ use std::{
 };
 
 use anyhow::{Context, Result, bail};
-use procfs::process::Process;
 use regex_lite::Regex;
 use rustix::{
-    fs::ioctl_ficlone,
+    fs::{AtFlags, CWD, FileType, Gid, Mode, Uid, chownat, ioctl_ficlone, mknodat},
     mount::{MountFlags, mount},
 };
+use sha2::{Digest, Sha256};
 use tracing::{Event, Subscriber};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use extattr::{Flags as XattrFlags, lsetxattr};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     EnvFilter,
@@ -30,19 +34,21 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
-use crate::defs::{self, TMPFS_CANDIDATES};
-
-#[cfg(any(target_os = "linux", target_os = "android"))]
-use extattr::{Flags as XattrFlags, lsetxattr};
-
-const SELINUX_XATTR: &str = "security.selinux";
+use crate::{
+    conf::config::{ErofsCompressor, ErofsOptions},
+    defs::{self, TMPFS_CANDIDATES},
+    erofs,
+    mount::{
+        loopdev::{LoopDevice, LoopOptions},
+        mountinfo::MountTable,
+    },
+    selinux::{DEFAULT_CONTEXT, SELINUX_XATTR, lsetfilecon, setfilecon_strict},
+};
 
 #[allow(dead_code)]
 
 const XATTR_TEST_FILE: &str = ".xattr_test";
 
-const DEFAULT_CONTEXT: &str = "u:object_r:system_file:s0";
-
 const OVERLAY_TEST_XATTR: &str = "trusted.overlay.test";
 
 static MODULE_ID_REGEX: OnceLock<Regex> = OnceLock::new();
@@ -147,58 +153,12 @@ pub fn check_zygisksu_enforce_status() -> bool {
         .unwrap_or(false)
 }
 
-pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
-    #[cfg(any(target_os = "linux", target_os = "android"))]
-    {
-        if let Err(e) = lsetxattr(&path, SELINUX_XATTR, con, XattrFlags::empty()) {
-            let io_err = std::io::Error::from(e);
-
-            log::debug!(
-                "lsetfilecon: {} -> {} failed: {}",
-                path.as_ref().display(),
-                con,
-                io_err
-            );
-        }
-    }
+pub fn getprop(name: &str) -> Option<String> {
+    let output = Command::new("getprop").arg(name).output().ok()?;
 
-    #[cfg(not(any(target_os = "linux", target_os = "android")))]
-    {
-        let _ = path;
-
-        let _ = con;
-    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
-    Ok(())
-}
-
-#[cfg(any(target_os = "linux", target_os = "android"))]
-
-pub fn lgetfilecon<P: AsRef<Path>>(path: P) -> Result<String> {
-    let con = extattr::lgetxattr(&path, SELINUX_XATTR).with_context(|| {
-        format!(
-            "Failed to get SELinux context for {}",
-            path.as_ref().display()
-        )
-    })?;
-
-    Ok(String::from_utf8_lossy(&con).to_string())
-}
-
-#[cfg(not(any(target_os = "linux", target_os = "android")))]
-
-pub fn lgetfilecon<P: AsRef<Path>>(_path: P) -> Result<String> {
-    Ok(DEFAULT_CONTEXT.to_string())
-}
-
-pub fn copy_path_context<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result<()> {
-    let context = if src.as_ref().exists() {
-        lgetfilecon(&src).unwrap_or_else(|_| DEFAULT_CONTEXT.to_string())
-    } else {
-        DEFAULT_CONTEXT.to_string()
-    };
-
-    lsetfilecon(dst, &context)
+    (output.status.success() && !value.is_empty()).then_some(value)
 }
 
 pub fn ensure_dir_exists<T: AsRef<Path>>(dir: T) -> Result<()> {
@@ -250,9 +210,14 @@ pub fn is_xattr_supported(path: &Path) -> bool {
         return false;
     }
 
-    let result = lsetfilecon(&test_file, "u:object_r:system_file:s0");
+    let supported = match setfilecon_strict(&test_file, DEFAULT_CONTEXT) {
+        Ok(_) => true,
+        Err(e) => {
+            log::debug!("XATTR Check: {:#}", e);
 
-    let supported = result.is_ok();
+            false
+        }
+    };
 
     let _ = remove_file(test_file);
 
@@ -307,35 +272,34 @@ pub fn is_overlay_xattr_supported(path: &Path) -> bool {
 }
 
 pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
-    let path_str = path.as_ref().to_string_lossy();
-
-    let search = path_str.trim_end_matches('/');
-
-    if let Ok(process) = Process::myself()
-        && let Ok(mountinfo) = process.mountinfo()
-    {
-        return mountinfo
-            .into_iter()
-            .any(|m| m.mount_point.to_string_lossy() == search);
-    }
-
-    if let Ok(content) = fs::read_to_string("/proc/mounts") {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-
-            if parts.len() > 1 && parts[1] == search {
-                return true;
-            }
-        }
-    }
+    MountTable::read_self()
+        .map(|table| table.is_mount_point(path))
+        .unwrap_or(false)
+}
 
-    false
+pub fn mem_total() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| {
+            rest.trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kb| kb * 1024)
 }
 
-pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
+pub fn mount_tmpfs(target: &Path, source: &str, size: Option<u64>) -> Result<()> {
     ensure_dir_exists(target)?;
 
-    let data = CString::new("mode=0755")?;
+    let data = match size {
+        Some(bytes) => CString::new(format!("mode=0755,size={}", bytes))?,
+        None => CString::new("mode=0755")?,
+    };
 
     mount(
         source,
@@ -349,23 +313,48 @@ pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn mount_image(image_path: &Path, target: &Path) -> Result<()> {
+pub fn mount_image(image_path: &Path, target: &Path) -> Result<LoopDevice> {
     ensure_dir_exists(target)?;
 
     lsetfilecon(image_path, "u:object_r:ksu_file:s0").ok();
 
-    let status = Command::new("mount")
-        .args(["-t", "ext4", "-o", "loop,rw,noatime"])
-        .arg(image_path)
-        .arg(target)
-        .status()
-        .context("Failed to execute mount command")?;
+    mount_loop(
+        image_path,
+        target,
+        "ext4",
+        MountFlags::NOATIME,
+        LoopOptions {
+            read_only: false,
+            direct_io: true,
+        },
+    )
+}
 
-    if !status.success() {
-        bail!("Mount command failed");
-    }
+fn mount_loop(
+    image_path: &Path,
+    target: &Path,
+    fs_type: &str,
+    flags: MountFlags,
+    options: LoopOptions,
+) -> Result<LoopDevice> {
+    let (device, _fd) = LoopDevice::attach(image_path, options)?;
 
-    Ok(())
+    mount(&device.path, target, fs_type, flags, c"").with_context(|| {
+        format!(
+            "Failed to mount {} on {}",
+            device.path.display(),
+            target.display()
+        )
+    })?;
+
+    log::debug!(
+        "Mounted {} via {} on {}",
+        image_path.display(),
+        device.path.display(),
+        target.display()
+    );
+
+    Ok(device)
 }
 
 pub fn repair_image(image_path: &Path) -> Result<()> {
@@ -386,6 +375,46 @@ pub fn repair_image(image_path: &Path) -> Result<()> {
     Ok(())
 }
 
+pub fn resize_image(image_path: &Path, size: Option<u64>) -> Result<()> {
+    let mut command = Command::new("resize2fs");
+
+    command.arg(image_path);
+
+    if let Some(size) = size {
+        command.arg(format!("{}K", size / 1024));
+    }
+
+    log::info!("Running resize2fs on {}", image_path.display());
+
+    let status = command.status().context("Failed to execute resize2fs")?;
+
+    if !status.success() {
+        bail!("resize2fs failed with exit code: {:?}", status.code());
+    }
+
+    Ok(())
+}
+
+pub fn sha256_file(path: &Path) -> Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+
+    let mut hasher = Sha256::new();
+
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
 pub fn reflink_or_copy(src: &Path, dest: &Path) -> Result<u64> {
     let src_file = File::open(src)?;
 
@@ -412,62 +441,248 @@ pub fn reflink_or_copy(src: &Path, dest: &Path) -> Result<u64> {
     fs::copy(src, dest).map_err(|e| e.into())
 }
 
-fn native_cp_r(src: &Path, dst: &Path) -> Result<()> {
-    if !dst.exists() {
-        create_dir_all(dst)?;
+const MAX_LOST_REPORTED: usize = 8;
+
+#[derive(Debug, Default)]
+
+pub struct CopyReport {
+    pub files: usize,
+    pub hardlinks: usize,
+    pub special: usize,
+    pub lost: Vec<String>,
+}
+
+impl CopyReport {
+    pub fn log_summary(&self, label: &str) {
+        log::debug!(
+            "{}: copied {} files, {} hardlinks, {} special files",
+            label,
+            self.files,
+            self.hardlinks,
+            self.special
+        );
+
+        if self.lost.is_empty() {
+            return;
+        }
+
+        log::warn!(
+            "{}: {} attributes could not be preserved",
+            label,
+            self.lost.len()
+        );
+
+        for item in self.lost.iter().take(MAX_LOST_REPORTED) {
+            log::warn!("  {}", item);
+        }
+
+        if self.lost.len() > MAX_LOST_REPORTED {
+            log::warn!("  ... and {} more", self.lost.len() - MAX_LOST_REPORTED);
+        }
+    }
+}
+
+#[derive(Default)]
 
-        let src_meta = src.metadata()?;
+pub struct TreeCopier {
+    links: HashMap<(u64, u64), PathBuf>,
+    report: CopyReport,
+}
 
-        fs::set_permissions(dst, src_meta.permissions())?;
+impl TreeCopier {
+    pub fn copy_dir(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let meta = fs::symlink_metadata(src)?;
 
-        lsetfilecon(dst, DEFAULT_CONTEXT)?;
+        if !dst.is_dir() {
+            create_dir_all(dst)?;
+        }
+
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+
+            self.copy_entry(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+
+        self.apply_metadata(src, dst, &meta);
+
+        Ok(())
     }
 
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
+    pub fn copy_entry(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let meta = fs::symlink_metadata(src)?;
 
-        let ft = entry.file_type()?;
+        let file_type = meta.file_type();
 
-        let src_path = entry.path();
+        if file_type.is_dir() {
+            return self.copy_dir(src, dst);
+        }
 
-        let dst_path = dst.join(entry.file_name());
+        if fs::symlink_metadata(dst).is_ok() {
+            remove_file(dst)?;
+        }
 
-        if ft.is_dir() {
-            native_cp_r(&src_path, &dst_path)?;
-        } else if ft.is_symlink() {
-            let link_target = fs::read_link(&src_path)?;
+        let identity = (meta.dev(), meta.ino());
 
-            if dst_path.exists() {
-                remove_file(&dst_path)?;
-            }
+        if meta.nlink() > 1
+            && let Some(first) = self.links.get(&identity)
+        {
+            fs::hard_link(first, dst)
+                .with_context(|| format!("Failed to link {}", dst.display()))?;
 
-            symlink(&link_target, &dst_path)?;
+            self.report.hardlinks += 1;
 
-            let _ = lsetfilecon(&dst_path, DEFAULT_CONTEXT);
+            return Ok(());
+        }
+
+        if file_type.is_symlink() {
+            symlink(fs::read_link(src)?, dst)?;
+        } else if file_type.is_file() {
+            reflink_or_copy(src, dst)?;
+
+            self.report.files += 1;
+        } else if file_type.is_socket() {
+            self.report
+                .lost
+                .push(format!("{}: sockets are not copied", src.display()));
+
+            return Ok(());
         } else {
-            reflink_or_copy(&src_path, &dst_path)?;
+            mknodat(
+                CWD,
+                dst,
+                FileType::from_raw_mode(meta.mode()),
+                Mode::from_raw_mode(meta.mode()),
+                meta.rdev(),
+            )
+            .with_context(|| format!("Failed to create node {}", dst.display()))?;
+
+            self.report.special += 1;
+        }
+
+        self.apply_metadata(src, dst, &meta);
 
-            lsetfilecon(&dst_path, DEFAULT_CONTEXT)?;
+        if meta.nlink() > 1 {
+            self.links.insert(identity, dst.to_path_buf());
         }
+
+        Ok(())
     }
 
-    Ok(())
+    pub fn copy_metadata(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let meta = fs::symlink_metadata(src)?;
+
+        self.apply_metadata(src, dst, &meta);
+
+        Ok(())
+    }
+
+    pub fn relocate(&mut self, meta: &fs::Metadata, dst: &Path) {
+        if let Some(first) = self.links.get_mut(&(meta.dev(), meta.ino())) {
+            *first = dst.to_path_buf();
+        }
+    }
+
+    pub fn finish(self) -> CopyReport {
+        self.report
+    }
+
+    fn apply_metadata(&mut self, src: &Path, dst: &Path, meta: &fs::Metadata) {
+        if let Err(e) = chownat(
+            CWD,
+            dst,
+            Some(Uid::from_raw(meta.uid())),
+            Some(Gid::from_raw(meta.gid())),
+            AtFlags::SYMLINK_NOFOLLOW,
+        ) {
+            self.report.lost.push(format!(
+                "{}: ownership {}:{} ({})",
+                dst.display(),
+                meta.uid(),
+                meta.gid(),
+                e
+            ));
+        }
+
+        if !meta.file_type().is_symlink()
+            && let Err(e) = fs::set_permissions(dst, meta.permissions())
+        {
+            self.report.lost.push(format!(
+                "{}: mode {:o} ({})",
+                dst.display(),
+                meta.mode() & 0o7777,
+                e
+            ));
+        }
+
+        self.report.lost.extend(copy_xattrs(src, dst));
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+
+pub fn set_xattrs(path: &Path, mut xattrs: BTreeMap<String, Vec<u8>>) -> Vec<String> {
+    xattrs
+        .entry(SELINUX_XATTR.to_string())
+        .or_insert_with(|| DEFAULT_CONTEXT.as_bytes().to_vec());
+
+    xattrs
+        .into_iter()
+        .filter_map(|(name, value)| {
+            lsetxattr(path, &name, &value, XattrFlags::empty())
+                .err()
+                .map(|e| format!("{}: xattr {} ({})", path.display(), name, e))
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+
+pub fn set_xattrs(path: &Path, xattrs: BTreeMap<String, Vec<u8>>) -> Vec<String> {
+    let context = xattrs
+        .get(SELINUX_XATTR)
+        .map(|value| {
+            String::from_utf8_lossy(value)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .unwrap_or_else(|| DEFAULT_CONTEXT.to_string());
+
+    match lsetfilecon(path, &context) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![format!("{}: selinux context ({:#})", path.display(), e)],
+    }
+}
+
+fn copy_xattrs(src: &Path, dst: &Path) -> Vec<String> {
+    set_xattrs(dst, erofs::read_xattrs(src))
 }
 
-pub fn sync_dir(src: &Path, dst: &Path) -> Result<()> {
+pub fn copy_entry(src: &Path, dst: &Path) -> Result<CopyReport> {
+    let mut copier = TreeCopier::default();
+
+    copier.copy_entry(src, dst)?;
+
+    Ok(copier.finish())
+}
+
+pub fn sync_dir(src: &Path, dst: &Path) -> Result<CopyReport> {
     if !src.exists() {
-        return Ok(());
+        return Ok(CopyReport::default());
     }
 
     ensure_dir_exists(dst)?;
 
-    native_cp_r(src, dst).with_context(|| {
+    let mut copier = TreeCopier::default();
+
+    copier.copy_dir(src, dst).with_context(|| {
         format!(
             "Failed to natively sync {} to {}",
             src.display(),
             dst.display()
         )
-    })
+    })?;
+
+    Ok(copier.finish())
 }
 
 fn is_ok_empty<P: AsRef<Path>>(dir: P) -> bool {
@@ -524,13 +739,39 @@ pub fn ensure_temp_dir(temp_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn is_erofs_supported() -> bool {
-    fs::read_to_string("/proc/filesystems")
-        .map(|content| content.contains("erofs"))
-        .unwrap_or(false)
+fn mkfs_erofs_args(options: &ErofsOptions) -> Vec<String> {
+    let mut args = Vec::new();
+
+    let compressor = options.compressor.unwrap_or(ErofsCompressor::Lz4hc);
+
+    if compressor != ErofsCompressor::None {
+        match options.level {
+            Some(level) => args.push(format!("-z{},{}", compressor.as_str(), level)),
+            None => args.push(format!("-z{}", compressor.as_str())),
+        }
+    }
+
+    if let Some(cluster_size) = options.cluster_size {
+        args.push(format!("-C{}", cluster_size));
+    }
+
+    let extended: Vec<&str> = [(options.fragments, "fragments"), (options.dedupe, "dedupe")]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+
+    if !extended.is_empty() {
+        args.push(format!("-E{}", extended.join(",")));
+    }
+
+    if let Some(timestamp) = options.timestamp {
+        args.push(format!("-T{}", timestamp));
+    }
+
+    args
 }
 
-pub fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
+pub fn create_erofs_image(src_dir: &Path, image_path: &Path, options: &ErofsOptions) -> Result<()> {
     let mkfs_bin = Path::new("/data/adb/metamodule/tools/mkfs.erofs");
 
     let cmd_name = if mkfs_bin.exists() {
@@ -539,11 +780,16 @@ pub fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
         std::ffi::OsStr::new("mkfs.erofs")
     };
 
-    log::info!("Packing EROFS image: {}", image_path.display());
+    let args = mkfs_erofs_args(options);
+
+    log::info!(
+        "Packing EROFS image: {} [{}]",
+        image_path.display(),
+        args.join(" ")
+    );
 
     let output = Command::new(cmd_name)
-        .arg("-z")
-        .arg("lz4hc")
+        .args(&args)
         .arg(image_path)
         .arg(src_dir)
         .stdout(Stdio::piped())
@@ -578,21 +824,19 @@ pub fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn mount_erofs_image(image_path: &Path, target: &Path) -> Result<()> {
+pub fn mount_erofs_image(image_path: &Path, target: &Path) -> Result<LoopDevice> {
     ensure_dir_exists(target)?;
 
     lsetfilecon(image_path, "u:object_r:ksu_file:s0").ok();
 
-    let status = Command::new("mount")
-        .args(["-t", "erofs", "-o", "loop,ro,nodev,noatime"])
-        .arg(image_path)
-        .arg(target)
-        .status()
-        .context("Failed to execute mount command for EROFS")?;
-
-    if !status.success() {
-        bail!("EROFS Mount command failed");
-    }
-
-    Ok(())
+    mount_loop(
+        image_path,
+        target,
+        "erofs",
+        MountFlags::RDONLY | MountFlags::NODEV | MountFlags::NOATIME,
+        LoopOptions {
+            read_only: true,
+            direct_io: true,
+        },
+    )
 }
@@ -5,7 +5,7 @@
 
 import { APP_VERSION } from './constants_gen';
 import { DEFAULT_CONFIG } from './constants';
-import type { AppConfig, DeviceInfo, Module, StorageStatus, SystemInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo } from './types';
+import type { AppConfig, DeviceInfo, Module, StorageStatus, SystemInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo, SiloDiff } from './types';
 
 const delay = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
 
@@ -176,6 +176,26 @@ export const MockAPI = {
     await delay(500);
     console.log(`[Mock] Restored silo: ${siloId}`);
   },
+  async exportSilo(siloId: string): Promise<string> {
+    await delay(500);
+    console.log(`[Mock] Exported silo: ${siloId}`);
+    return `Silo ${siloId} exported to /data/adb/meta-hybrid/granary/exports/${siloId}.mhsilo`;
+  },
+  async importSilo(path: string): Promise<void> {
+    await delay(500);
+    console.log(`[Mock] Imported silo archive: ${path}`);
+  },
+  async diffSilos(from: string, to: string): Promise<SiloDiff | null> {
+    await delay(300);
+    return {
+        from,
+        to,
+        config: [{ key: 'verbose', before: false, after: true }],
+        winnowing: [],
+        rules: [],
+        modules: [{ key: 'example_module', before: 'enabled', after: 'disabled' }]
+    };
+  },
 
   async setWinnowingRule(path: string, moduleId: string): Promise<void> {
     await delay(300);
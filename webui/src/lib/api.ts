@@ -6,7 +6,7 @@
 import { DEFAULT_CONFIG, PATHS } from './constants';
 import { APP_VERSION } from './constants_gen';
 import { MockAPI } from './api.mock';
-import type { AppConfig, Module, StorageStatus, SystemInfo, DeviceInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo } from './types';
+import type { AppConfig, Module, StorageStatus, SystemInfo, DeviceInfo, ModuleRules, ConflictEntry, DiagnosticIssue, Silo, SiloDiff } from './types';
 
 interface KsuExecResult {
   errno: number;
@@ -78,6 +78,9 @@ interface AppAPI {
   createSilo: (reason: string) => Promise<void>;
   deleteSilo: (siloId: string) => Promise<void>;
   restoreSilo: (siloId: string) => Promise<void>;
+  exportSilo: (siloId: string) => Promise<string>;
+  importSilo: (path: string) => Promise<void>;
+  diffSilos: (from: string, to: string) => Promise<SiloDiff | null>;
   setWinnowingRule: (path: string, moduleId: string) => Promise<void>;
 }
 
@@ -146,7 +149,9 @@ const RealAPI: AppAPI = {
           type: state.storage_mode || 'unknown',
           percent: `${state.storage_percent ?? 0}%`,
           size: formatBytes(state.storage_total ?? 0),
-          used: formatBytes(state.storage_used ?? 0)
+          used: formatBytes(state.storage_used ?? 0),
+          modules: state.module_usage ?? [],
+          dedup: state.storage_dedup
         };
       }
     } catch (e) {}
@@ -271,6 +276,27 @@ const RealAPI: AppAPI = {
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(stderr);
   },
+  exportSilo: async (siloId: string): Promise<string> => {
+    if (!ksuExec) return '';
+    const cmd = `${PATHS.BINARY} system-action --action granary-export --value "${siloId}"`;
+    const { errno, stdout, stderr } = await ksuExec(cmd);
+    if (errno !== 0) throw new Error(stderr);
+    return stdout.trim();
+  },
+  importSilo: async (path: string): Promise<void> => {
+    if (!ksuExec) return;
+    const cmd = `${PATHS.BINARY} system-action --action granary-import --value "${path}"`;
+    const { errno, stderr } = await ksuExec(cmd);
+    if (errno !== 0) throw new Error(stderr);
+  },
+  diffSilos: async (from: string, to: string): Promise<SiloDiff | null> => {
+    if (!ksuExec) return null;
+    try {
+        const { errno, stdout } = await ksuExec(`${PATHS.BINARY} system-action --action granary-diff --value "${from}:${to}"`);
+        if (errno === 0 && stdout) return JSON.parse(stdout);
+    } catch {}
+    return null;
+  },
   setWinnowingRule: async (path: string, moduleId: string): Promise<void> => {
     if (!ksuExec) return;
     const cmd = `${PATHS.BINARY} system-action --action winnow-set --value "${path}:${moduleId}"`;
@@ -24,7 +24,9 @@ export const DEFAULT_CONFIG: AppConfig = {
   },
   granary: {
     max_backups: 20,
-    retention_days: 0
+    retention_days: 0,
+    snapshot_modules: false,
+    module_snapshots: 3
   }
 };
 
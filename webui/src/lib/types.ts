@@ -6,8 +6,33 @@
 export interface GranaryConfig {
   max_backups: number;
   retention_days: number;
+  snapshot_modules: boolean;
+  module_snapshots: number;
 }
 
+export type StorageKind = 'tmpfs' | 'ext4' | 'erofs' | 'direct';
+
+export type ErofsCompressor = 'none' | 'lz4' | 'lz4hc' | 'lzma' | 'deflate';
+
+export interface ErofsOptions {
+  compressor?: ErofsCompressor;
+  level?: number;
+  cluster_size?: number;
+  fragments?: boolean;
+  dedupe?: boolean;
+  timestamp?: number;
+}
+
+export interface StorageConfig {
+  backends: StorageKind[];
+  shrink_ext4?: boolean;
+  tmpfs_budget?: string | number;
+  erofs?: ErofsOptions;
+  sync_hash?: boolean;
+}
+
+export type PropagationMode = 'inherit' | 'shared' | 'slave' | 'private';
+
 export interface AppConfig {
   moduledir: string;
   mountsource: string;
@@ -22,6 +47,8 @@ export interface AppConfig {
   logfile?: string;
   winnowing?: Record<string, string>;
   granary: GranaryConfig;
+  propagation?: Record<string, PropagationMode>;
+  storage?: StorageConfig;
 }
 
 export type MountMode = 'overlay' | 'magic' | 'ignore';
@@ -44,13 +71,28 @@ export interface Module {
   source_path?: string;
 }
 
+export interface ModuleUsage {
+  id: string;
+  apparent_size: number;
+  disk_size: number;
+  files: number;
+  partitions: string[];
+}
+
+export interface DedupStats {
+  files: number;
+  bytes_saved: number;
+}
+
 export interface StorageStatus {
   size: string;
   used: string;
   percent: string;
-  type: 'tmpfs' | 'ext4' | 'erofs' | 'unknown' | null;
+  type: 'tmpfs' | 'ext4' | 'erofs' | 'direct' | 'unknown' | null;
   error?: string;
   hymofs_available?: boolean;
+  modules?: ModuleUsage[];
+  dedup?: DedupStats;
 }
 
 export interface SystemInfo {
@@ -101,6 +143,33 @@ export interface Silo {
   config_snapshot: AppConfig;
   raw_config?: string;
   raw_state?: string;
+  modules?: SiloModuleFlags[];
+  rules?: Record<string, string>;
+  module_trees?: Record<string, string>;
+  module_versions?: Record<string, string>;
+}
+
+export interface SiloChange<T> {
+  key: string;
+  before: T | null;
+  after: T | null;
+}
+
+export interface SiloDiff {
+  from: string;
+  to: string;
+  config: SiloChange<unknown>[];
+  winnowing: SiloChange<string>[];
+  rules: { module: string; changes: SiloChange<unknown>[] }[];
+  modules: SiloChange<string>[];
+  notes?: string[];
+}
+
+export interface SiloModuleFlags {
+  id: string;
+  disable: boolean;
+  remove: boolean;
+  skip_mount: boolean;
 }
 
 export interface DiagnosticIssue {